@@ -0,0 +1,406 @@
+//! JSON API under `/api/v1/`
+//!
+//! Errors are returned as `{"code": ..., "message": ...}`, where `code` is
+//! one of [`ApiError`]'s stable codes.
+//!
+//! Requests are authenticated with the session cookie set by
+//! `POST /api/v1/login`. Requests other than `GET` must have a
+//! `Content-Type: application/json` header, which is what protects them
+//! from CSRF.
+
+use anyhow::Context as _;
+use astra::ResponseBuilder;
+use hyper::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Access, Item, ItemData, ItemId, ItemValue, ListId, ListValue};
+use crate::response::ResponseBuilderExt;
+use crate::routes::{param, ItemOrder};
+use crate::service::{
+    BadRequestError, ConflictError, ForbiddenError, NotFoundError, Service, Session,
+    UnauthorizedError,
+};
+use crate::util;
+
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    #[serde(skip)]
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn into_response(self) -> astra::Response {
+        ResponseBuilder::new().status(self.status).body_json(&self)
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        if error.is::<NotFoundError>() {
+            return Self {
+                status: StatusCode::NOT_FOUND,
+                code: "not_found",
+                message: format!("{error:#}"),
+            };
+        }
+        if error.is::<BadRequestError>() {
+            return Self {
+                status: StatusCode::BAD_REQUEST,
+                code: "bad_request",
+                message: format!("{error:#}"),
+            };
+        }
+        if error.is::<UnauthorizedError>() {
+            return Self {
+                status: StatusCode::UNAUTHORIZED,
+                code: "unauthorized",
+                message: format!("{error:#}"),
+            };
+        }
+        if error.is::<ForbiddenError>() {
+            return Self {
+                status: StatusCode::FORBIDDEN,
+                code: "forbidden",
+                message: format!("{error:#}"),
+            };
+        }
+        if error.is::<ConflictError>() {
+            return Self {
+                status: StatusCode::CONFLICT,
+                code: "conflict",
+                message: format!("{error:#}"),
+            };
+        }
+        tracing::warn!(%error, "API handler error");
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            code: "internal_error",
+            message: "Internal Server Error".into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiLogin {
+    username: String,
+    password: String,
+}
+
+/// The session a login started, its cookie being set too
+#[derive(Debug, Serialize)]
+pub struct ApiSession {
+    /// Sent as the `X-CSRF-Token` header, it replaces the json content type
+    csrf_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiList {
+    id: ListId,
+    #[serde(flatten)]
+    value: ListValue,
+    /// Access of the requesting user
+    access: Access,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiItem {
+    id: ItemId,
+    list_id: ListId,
+    /// Item this is a sub-item of
+    parent: Option<ItemId>,
+    #[serde(flatten)]
+    data: ItemData,
+    revision: u64,
+}
+
+impl From<Item> for ApiItem {
+    fn from(item: Item) -> Self {
+        Self {
+            id: item.id,
+            list_id: item.list_id,
+            parent: item.parent,
+            data: item.data,
+            revision: item.revision,
+        }
+    }
+}
+
+impl ApiItem {
+    pub fn from_value(id: ItemId, value: ItemValue) -> Self {
+        Item::from_value(id, value).into()
+    }
+}
+
+/// A new item, fields other than `title` being optional
+#[derive(Debug, Deserialize)]
+pub struct ApiItemCreate {
+    title: String,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Create it already done
+    #[serde(default)]
+    completed: bool,
+    due: Option<String>,
+}
+
+impl From<ApiItemCreate> for ItemData {
+    fn from(item: ApiItemCreate) -> Self {
+        Self {
+            title: item.title,
+            body: item.body,
+            tags: item.tags,
+            completed_at: item.completed.then(util::now_unix_secs),
+            due: item.due,
+        }
+    }
+}
+
+/// Item update, changing only the fields given
+///
+/// Rejected with a `conflict` if `revision` is given and the item was changed
+/// since.
+#[derive(Debug, Deserialize)]
+pub struct ApiItemUpdate {
+    title: Option<String>,
+    body: Option<String>,
+    tags: Option<Vec<String>>,
+    /// Mark the item done, or not done
+    completed: Option<bool>,
+    /// `null` removes the due date
+    #[serde(default, deserialize_with = "present")]
+    due: Option<Option<String>>,
+    revision: Option<u64>,
+}
+
+impl ApiItemUpdate {
+    fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.body.is_none()
+            && self.tags.is_none()
+            && self.completed.is_none()
+            && self.due.is_none()
+    }
+
+    /// `data` with the fields given changed
+    fn apply(self, data: &ItemData) -> ItemData {
+        let completed_at = match self.completed {
+            Some(true) => data.completed_at.or_else(|| Some(util::now_unix_secs())),
+            Some(false) => None,
+            None => data.completed_at,
+        };
+        ItemData {
+            title: self.title.unwrap_or_else(|| data.title.clone()),
+            body: self.body.unwrap_or_else(|| data.body.clone()),
+            tags: self.tags.unwrap_or_else(|| data.tags.clone()),
+            completed_at,
+            due: self.due.unwrap_or_else(|| data.due.clone()),
+        }
+    }
+}
+
+/// Deserialize a field that is present, telling `null` (`Some(None)`) from
+/// a missing field (`None`)
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiItemBatchOrder {
+    prev: Option<ItemId>,
+    items: Vec<ItemId>,
+    next: Option<ItemId>,
+    list: Option<ListId>,
+    parent: Option<ItemId>,
+}
+
+fn json_body<T>(req: &mut astra::Request) -> anyhow::Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    serde_json::from_reader(req.body_mut().reader()).context(BadRequestError("invalid json body"))
+}
+
+/// Render a handler error as a json response
+pub fn error_response(error: anyhow::Error) -> astra::Response {
+    ApiError::from(error).into_response()
+}
+
+impl Service {
+    pub fn api_login(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let login: ApiLogin = json_body(req)?;
+        let Some(user_id) = self.authenticate(&login.username, &login.password)? else {
+            return Err(UnauthorizedError.into());
+        };
+        self.create_inbox_if_missing(user_id)?;
+        let new_session = self.login_session(session.id, user_id)?;
+        let mut resp = ResponseBuilder::new().body_json(&ApiSession {
+            csrf_token: new_session.csrf_token(),
+        });
+        resp.headers_mut()
+            .insert(header::SET_COOKIE, self.session_cookie(&new_session));
+        Ok(resp)
+    }
+
+    pub fn api_list_list(
+        &self,
+        _req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let lists: Vec<_> = self
+            .read_lists(session.user_id()?)?
+            .into_iter()
+            .map(|(id, value, access)| ApiList { id, value, access })
+            .collect();
+        Ok(ResponseBuilder::new().body_json(&lists))
+    }
+
+    pub fn api_list_create(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let value: ListValue = json_body(req)?;
+        let id = self.create_list(session.user_id()?, value.clone())?;
+        Ok(ResponseBuilder::new()
+            .status(StatusCode::CREATED)
+            .body_json(&ApiList {
+                id,
+                value,
+                access: Access::Owner,
+            }))
+    }
+
+    pub fn api_item_list(
+        &self,
+        _req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let list_id: ListId = param(params, "list_id")?;
+        let user_id = session.user_id()?;
+        self.load_list(user_id, list_id)?;
+        let items: Vec<ApiItem> = self
+            .read_items(user_id, list_id)?
+            .into_iter()
+            .map(ApiItem::from)
+            .collect();
+        Ok(ResponseBuilder::new().body_json(&items))
+    }
+
+    pub fn api_item_create(
+        &self,
+        req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let list_id: ListId = param(params, "list_id")?;
+        let item: ApiItemCreate = json_body(req)?;
+        let user_id = session.user_id()?;
+        let id = self.create_item(user_id, list_id, item.into())?;
+        let value = self.load_item(user_id, id)?;
+        Ok(ResponseBuilder::new()
+            .status(StatusCode::CREATED)
+            .body_json(&ApiItem::from_value(id, value)))
+    }
+
+    pub fn api_item_get(
+        &self,
+        _req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let id: ItemId = param(params, "id")?;
+        let value = self.load_item(session.user_id()?, id)?;
+        Ok(ResponseBuilder::new().body_json(&ApiItem::from_value(id, value)))
+    }
+
+    pub fn api_item_update(
+        &self,
+        req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let id: ItemId = param(params, "id")?;
+        let update: ApiItemUpdate = json_body(req)?;
+        let user_id = session.user_id()?;
+        if !update.is_empty() {
+            let revision = update.revision;
+            self.change_item_data(user_id, id, revision, |current| update.apply(current))?;
+        }
+        let value = self.load_item(user_id, id)?;
+        Ok(ResponseBuilder::new().body_json(&ApiItem::from_value(id, value)))
+    }
+
+    pub fn api_item_delete(
+        &self,
+        _req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let id: ItemId = param(params, "id")?;
+        self.delete_item(session.user_id()?, id)?;
+        Ok(ResponseBuilder::new()
+            .status(StatusCode::NO_CONTENT)
+            .body(astra::Body::empty())
+            .expect("can't fail"))
+    }
+
+    pub fn api_item_order(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let order: ItemOrder = json_body(req)?;
+        let user_id = session.user_id()?;
+        self.change_item_order(
+            user_id,
+            order.prev,
+            order.curr,
+            order.next,
+            order.list,
+            order.parent,
+        )?;
+        let value = self.load_item(user_id, order.curr)?;
+        Ok(ResponseBuilder::new().body_json(&ApiItem::from_value(order.curr, value)))
+    }
+
+    pub fn api_item_order_batch(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let order: ApiItemBatchOrder = json_body(req)?;
+        let user_id = session.user_id()?;
+        self.change_items_order(
+            user_id,
+            order.prev,
+            &order.items,
+            order.next,
+            order.list,
+            order.parent,
+        )?;
+        let items = order
+            .items
+            .iter()
+            .map(|id| Ok(ApiItem::from_value(*id, self.load_item(user_id, *id)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(ResponseBuilder::new().body_json(&items))
+    }
+}
@@ -1,13 +1,15 @@
+use std::fmt;
 use std::fmt::Write as _;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use anyhow::{bail, Context};
-use redb::{ReadTransaction, TableDefinition, WriteTransaction};
+use anyhow::{bail, format_err, Context};
+use redb::{MultimapTableDefinition, ReadTransaction, TableDefinition, Value, WriteTransaction};
 use serde::{Deserialize, Serialize};
 
 use crate::sortid::SortId;
+use crate::util;
 
 #[derive(Clone)]
 pub struct Database(Arc<redb::Database>);
@@ -43,76 +45,321 @@ impl Database {
         Ok(res)
     }
 
+    /// Check the file for corruption, repairing it if possible
+    ///
+    /// Returns `false` if it had to be repaired. Needs the only handle to the
+    /// database.
+    pub fn check_integrity(&mut self) -> anyhow::Result<bool> {
+        Ok(self.exclusive()?.check_integrity()?)
+    }
+
+    /// Give unused space back to the filesystem
+    ///
+    /// Needs the only handle to the database.
+    pub fn compact(&mut self) -> anyhow::Result<()> {
+        while self.exclusive()?.compact()? {}
+        Ok(())
+    }
+
+    fn exclusive(&mut self) -> anyhow::Result<&mut redb::Database> {
+        Arc::get_mut(&mut self.0).ok_or_else(|| format_err!("database is still in use"))
+    }
+
     pub fn open(path: &PathBuf) -> anyhow::Result<Database> {
         Ok(Self::from(redb::Database::create(path).with_context(
             || format!("Failed to open database at {}", path.display()),
         )?))
     }
+
+    /// Like [`Database::open`], but without creating a missing file
+    pub fn open_existing(path: &PathBuf) -> anyhow::Result<Database> {
+        Ok(Self::from(redb::Database::open(path).with_context(
+            || format!("Failed to open database at {}", path.display()),
+        )?))
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct ItemId(pub u64);
+/// Define a `u64`-based id type, represented as `<prefix>N` in urls, html and serde
+macro_rules! define_id {
+    ($(#[$attr:meta])* $name:ident, $prefix:literal, $type_name:literal) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub u64);
+
+        impl $name {
+            pub(crate) fn increment(&self) -> Self {
+                Self(self.0 + 1)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!($prefix, "{}"), self.0)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let Some(num) = s.strip_prefix($prefix) else {
+                    bail!(concat!("does not start with '", $prefix, "'"));
+                };
+
+                Ok(Self(
+                    num.parse()
+                        .map_err(|_e| anyhow::format_err!("invalid number"))?,
+                ))
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                serializer.serialize_str(&self.to_string())
+            }
+        }
 
-impl ItemId {
-    pub(crate) fn increment(&self) -> Self {
-        Self(self.0 + 1)
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                use serde::de::Error;
+                String::deserialize(deserializer)
+                    .and_then(|s| <$name as FromStr>::from_str(&s).map_err(Error::custom))
+            }
+        }
+
+        impl maud::Render for $name {
+            fn render_to(&self, buffer: &mut String) {
+                write!(buffer, "{self}").expect("can't fail");
+            }
+        }
+
+        impl redb::Key for $name {
+            fn compare(data1: &[u8], data2: &[u8]) -> std::cmp::Ordering {
+                // bytes are little endian, so compare them as numbers
+                u64::from_bytes(data1).cmp(&u64::from_bytes(data2))
+            }
+        }
+
+        impl redb::Value for $name {
+            type SelfType<'a> = $name;
+
+            type AsBytes<'a> = [u8; 8];
+
+            fn fixed_width() -> Option<usize> {
+                u64::fixed_width()
+            }
+
+            fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+            where
+                Self: 'a,
+            {
+                Self(u64::from_bytes(data))
+            }
+
+            fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+            where
+                Self: 'a,
+                Self: 'b,
+            {
+                u64::as_bytes(&value.0)
+            }
+
+            fn type_name() -> redb::TypeName {
+                redb::TypeName::new($type_name)
+            }
+        }
+    };
+}
+
+define_id!(ItemId, "i-", "item-id");
+define_id!(
+    /// A named, separately ordered list of items
+    ListId,
+    "l-",
+    "list-id"
+);
+define_id!(
+    /// An account that can log in
+    UserId,
+    "u-",
+    "user-id"
+);
+
+/// Random, unguessable id of a browser session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionId(pub [u8; 32]);
+
+impl SessionId {
+    pub fn generate() -> Self {
+        Self(util::random_bytes())
     }
 }
 
-impl FromStr for ItemId {
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&util::hex_encode(&self.0))
+    }
+}
+
+impl FromStr for SessionId {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if !s.starts_with("i-") {
-            bail!("does not start with 'i-'");
-        }
-
         Ok(Self(
-            s.split_at(2)
-                .1
-                .parse()
-                .map_err(|_e| anyhow::format_err!("invalid number"))?,
+            util::hex_decode(s)?
+                .try_into()
+                .map_err(|_e| anyhow::format_err!("invalid length"))?,
         ))
     }
 }
 
-impl Serialize for ItemId {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+impl redb::Key for SessionId {
+    fn compare(data1: &[u8], data2: &[u8]) -> std::cmp::Ordering {
+        data1.cmp(data2)
+    }
+}
+
+impl redb::Value for SessionId {
+    type SelfType<'a> = SessionId;
+
+    type AsBytes<'a> = &'a [u8; 32];
+
+    fn fixed_width() -> Option<usize> {
+        Some(32)
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
     where
-        S: serde::Serializer,
+        Self: 'a,
     {
-        serializer.serialize_str(&format!("i-{}", self.0))
+        Self(data.try_into().expect("invalid session id length"))
     }
-}
 
-impl<'de> Deserialize<'de> for ItemId {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
     where
-        D: serde::Deserializer<'de>,
+        Self: 'a,
+        Self: 'b,
     {
-        use serde::de::Error;
-        String::deserialize(deserializer)
-            .and_then(|s| <ItemId as FromStr>::from_str(&s).map_err(Error::custom))
+        &value.0
     }
-}
 
-impl maud::Render for ItemId {
-    fn render_to(&self, buffer: &mut String) {
-        buffer.push_str("i-");
-        write!(buffer, "{}", self.0).expect("can't fail");
+    fn type_name() -> redb::TypeName {
+        redb::TypeName::new("session-id")
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionValue {
+    /// Unix timestamp (seconds) of the session creation
+    pub created_at: u64,
+    /// Unix timestamp (seconds) after which the session is no longer valid
+    pub expires_at: u64,
+    /// The logged in user, if any
+    pub user_id: Option<UserId>,
+    /// Random token state changing requests must carry
+    pub csrf_token: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserValue {
+    pub username: String,
+    /// Salted argon2 hash, in PHC string format
+    pub password_hash: String,
+    pub is_admin: bool,
+    /// Unix timestamp (seconds) of the account creation
+    pub created_at: u64,
+}
+
+/// What a user can do with a list, in increasing order
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Edit,
+    /// Can also share the list with others
+    Owner,
+}
+
 #[derive(Debug)]
 pub struct Item {
     pub id: ItemId,
+    pub list_id: ListId,
+    pub parent: Option<ItemId>,
     pub data: ItemData,
+    pub revision: u64,
+}
+
+impl Item {
+    pub fn from_value(id: ItemId, value: ItemValue) -> Self {
+        Self {
+            id,
+            list_id: value.list_id,
+            parent: value.parent,
+            data: value.data,
+            revision: value.revision,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ItemValue {
+    pub list_id: ListId,
     pub sort_id: SortId,
     pub data: ItemData,
+    /// Incremented on every change of `data`, to detect conflicting edits
+    pub revision: u64,
+    /// Item this is a sub-item of, in the same list
+    pub parent: Option<ItemId>,
+}
+
+impl ItemValue {
+    /// The keyspace `sort_id` orders the item in
+    pub fn siblings(&self) -> Siblings {
+        match self.parent {
+            Some(parent) => Siblings::Children(parent),
+            None => Siblings::List(self.list_id),
+        }
+    }
+}
+
+/// Keyspace of items ordered relative to each other: the top level items of
+/// a list, or the sub-items of an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Siblings {
+    /// Kept in [`ITEM_ORDER_TABLE`]
+    List(ListId),
+    /// Kept in [`ITEM_CHILD_ORDER_TABLE`]
+    Children(ItemId),
+}
+
+impl Siblings {
+    /// The item these are sub-items of, if any
+    pub fn parent(self) -> Option<ItemId> {
+        match self {
+            Siblings::List(_) => None,
+            Siblings::Children(item_id) => Some(item_id),
+        }
+    }
+}
+
+impl fmt::Display for Siblings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Siblings::List(list_id) => write!(f, "{list_id}"),
+            Siblings::Children(item_id) => write!(f, "{item_id}/children"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListValue {
+    pub name: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -120,17 +367,131 @@ pub struct ItemData {
     pub title: String,
     #[serde(default)]
     pub body: String,
+    pub tags: Vec<String>,
+    /// Unix timestamp (seconds) of when the item was marked done, if it is
+    pub completed_at: Option<u64>,
+    /// Date the item is due, as `YYYY-MM-DD`
+    pub due: Option<String>,
 }
 
-pub const ITEM_TABLE: TableDefinition<ItemId, ItemValue> = TableDefinition::new("item");
-pub const ITEM_ORDER_TABLE: TableDefinition<SortId, ItemId> = TableDefinition::new("item_order");
-
-impl redb::Key for ItemId {
-    fn compare(data1: &[u8], data2: &[u8]) -> std::cmp::Ordering {
-        data1.cmp(data2)
+impl ItemData {
+    /// Split comma separated `tags`, dropping empty and repeated ones
+    pub fn parse_tags(tags: &str) -> Vec<String> {
+        let mut parsed: Vec<String> = vec![];
+        for tag in tags.split(',').map(str::trim) {
+            if !tag.is_empty() && !parsed.iter().any(|t| t == tag) {
+                parsed.push(tag.to_owned());
+            }
+        }
+        parsed
     }
 }
 
+/// An item removed from the list, kept around so it can be restored
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrashedItem {
+    /// The value as it was at the time of deletion, including its old `sort_id`
+    pub value: ItemValue,
+    /// Unix timestamp (seconds) of the deletion
+    pub deleted_at: u64,
+}
+
+/// A change to an item, as recorded in the [`ITEM_EVENT_TABLE`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ItemEvent {
+    /// Unix timestamp (seconds) of the change
+    pub at: u64,
+    pub actor: UserId,
+    pub kind: ItemEventKind,
+    /// Data before the change, `None` if the item didn't exist
+    pub before: Option<ItemData>,
+    /// Data after the change, `None` if the item doesn't exist anymore
+    pub after: Option<ItemData>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ItemEventKind {
+    Create,
+    Update,
+    /// Moved from one position, possibly in another list or under another
+    /// parent, to another
+    Reorder {
+        from: (ListId, Option<ItemId>, SortId),
+        to: (ListId, Option<ItemId>, SortId),
+    },
+    Delete,
+    Restore,
+}
+
+/// The most recent change of a user that can be undone
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UndoValue {
+    /// The [`ITEM_EVENT_TABLE`] events the change consisted of
+    pub events: Vec<UndoEvent>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UndoEvent {
+    pub item_id: ItemId,
+    /// Sequence number of the event among those of the item
+    pub seq: u64,
+    /// Revision of the item right after the change, undoing an update of
+    /// an item changed since is a conflict
+    pub revision: u64,
+    /// Item the reordered item came after, `None` if it was the first one
+    ///
+    /// Its old `SortId` doesn't survive rebalancing, the neighbor does.
+    pub prev_id: Option<ItemId>,
+}
+
+/// Version of the database, see [`crate::migrate`]
+pub const DB_VERSION_TABLE: TableDefinition<(), u64> = TableDefinition::new("db_version");
+pub const LIST_TABLE: TableDefinition<ListId, ListValue> = TableDefinition::new("list");
+pub const ITEM_TABLE: TableDefinition<ItemId, ItemValue> = TableDefinition::new("item");
+/// Highest [`ItemId`] handed out so far, so ids of purged items aren't
+/// reused and don't inherit their history
+pub const LAST_ITEM_ID_TABLE: TableDefinition<(), ItemId> = TableDefinition::new("last_item_id");
+/// Ordering of items, each list being a separate keyspace
+///
+/// Values of each list are kept sorted by `(SortId, ItemId)`, so iterating
+/// them yields list items in order.
+pub const ITEM_ORDER_TABLE: MultimapTableDefinition<ListId, (SortId, ItemId)> =
+    MultimapTableDefinition::new("item_order");
+/// Ordering of sub-items, each parent item being a separate keyspace, like
+/// [`ITEM_ORDER_TABLE`]
+pub const ITEM_CHILD_ORDER_TABLE: MultimapTableDefinition<ItemId, (SortId, ItemId)> =
+    MultimapTableDefinition::new("item_child_order");
+pub const SESSION_TABLE: TableDefinition<SessionId, SessionValue> = TableDefinition::new("session");
+pub const USER_TABLE: TableDefinition<UserId, UserValue> = TableDefinition::new("user");
+/// Index of [`USER_TABLE`] by username
+pub const USER_NAME_TABLE: TableDefinition<&str, UserId> = TableDefinition::new("user_name");
+/// Append-only log of changes to each item, keyed by item and sequence
+/// number
+pub const ITEM_EVENT_TABLE: TableDefinition<(ItemId, u64), ItemEvent> =
+    TableDefinition::new("item_event");
+pub const UNDO_TABLE: TableDefinition<UserId, UndoValue> = TableDefinition::new("undo");
+/// Lists each user owns or has been shared
+pub const LIST_ACCESS_TABLE: TableDefinition<(UserId, ListId), Access> =
+    TableDefinition::new("list_access");
+/// Index of [`LIST_ACCESS_TABLE`] by list, for the users it's shared with
+pub const LIST_USER_TABLE: TableDefinition<(ListId, UserId), ()> =
+    TableDefinition::new("list_user");
+pub const ITEM_TRASH_TABLE: TableDefinition<ItemId, TrashedItem> =
+    TableDefinition::new("item_trash");
+/// Inverted index of item titles and bodies, mapping each term and item it
+/// appears in to its weight there
+///
+/// Items in the trash are not indexed.
+pub const ITEM_TERM_TABLE: TableDefinition<(&str, ItemId), u32> = TableDefinition::new("item_term");
+/// Index of items by each of their tags
+///
+/// Items in the trash are not indexed.
+pub const ITEM_TAG_TABLE: TableDefinition<(&str, ItemId), ()> = TableDefinition::new("item_tag");
+/// Index of items by their due date, soonest first
+///
+/// Items in the trash are not indexed.
+pub const ITEM_DUE_TABLE: TableDefinition<(&str, ItemId), ()> = TableDefinition::new("item_due");
+
 impl redb::Key for SortId {
     fn compare(data1: &[u8], data2: &[u8]) -> std::cmp::Ordering {
         SortId::cmp_raw(data1, data2)
@@ -166,60 +527,45 @@ impl redb::Value for SortId {
     }
 }
 
-impl redb::Value for ItemId {
-    type SelfType<'a> = ItemId;
-
-    type AsBytes<'a> = [u8; 8];
-
-    fn fixed_width() -> Option<usize> {
-        u64::fixed_width()
-    }
-
-    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
-    where
-        Self: 'a,
-    {
-        Self(u64::from_bytes(data))
-    }
-
-    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
-    where
-        Self: 'a,
-        Self: 'b,
-    {
-        u64::as_bytes(&value.0)
-    }
-
-    fn type_name() -> redb::TypeName {
-        redb::TypeName::new("item-id")
-    }
+/// Implement [`redb::Value`] for a type using `bincode` serialization
+macro_rules! impl_bincode_value {
+    ($t:ty, $name:literal) => {
+        impl redb::Value for $t {
+            type SelfType<'a> = $t;
+
+            type AsBytes<'a> = Vec<u8>;
+
+            fn fixed_width() -> Option<usize> {
+                None
+            }
+
+            fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+            where
+                Self: 'a,
+            {
+                bincode::deserialize(data).expect("bincode deserialization error")
+            }
+
+            fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+            where
+                Self: 'a,
+                Self: 'b,
+            {
+                bincode::serialize(value).expect("bincode serialization error")
+            }
+
+            fn type_name() -> redb::TypeName {
+                redb::TypeName::new($name)
+            }
+        }
+    };
 }
 
-impl redb::Value for ItemValue {
-    type SelfType<'a> = ItemValue;
-
-    type AsBytes<'a> = Vec<u8>;
-
-    fn fixed_width() -> Option<usize> {
-        None
-    }
-
-    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
-    where
-        Self: 'a,
-    {
-        bincode::deserialize(data).expect("bincode deserialization error")
-    }
-
-    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
-    where
-        Self: 'a,
-        Self: 'b,
-    {
-        bincode::serialize(value).expect("bincode serialization error")
-    }
-
-    fn type_name() -> redb::TypeName {
-        redb::TypeName::new("item-value")
-    }
-}
+impl_bincode_value!(ItemValue, "item-value");
+impl_bincode_value!(ListValue, "list-value");
+impl_bincode_value!(TrashedItem, "trashed-item");
+impl_bincode_value!(SessionValue, "session-value");
+impl_bincode_value!(UserValue, "user-value");
+impl_bincode_value!(Access, "access");
+impl_bincode_value!(ItemEvent, "item-event");
+impl_bincode_value!(UndoValue, "undo-value");
@@ -0,0 +1,222 @@
+//! Versioned JSON format of the whole database, for backups and for moving
+//! data between instances
+//!
+//! Item history, the trash and sessions are not part of it.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Access, ItemData, ItemId, ListId, Siblings, UserId, UserValue};
+use crate::service::{check_item_data, BadRequestError};
+use crate::sortid::SortId;
+use crate::util;
+
+/// Bumped on incompatible changes of the format
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub version: u32,
+    pub users: Vec<ExportUser>,
+    pub lists: Vec<ExportList>,
+    /// Each parent comes before its sub-items
+    pub items: Vec<ExportItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportUser {
+    pub id: UserId,
+    #[serde(flatten)]
+    pub value: UserValue,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportList {
+    pub id: ListId,
+    pub name: String,
+    pub shares: Vec<ExportShare>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportShare {
+    pub user: UserId,
+    pub access: Access,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportItem {
+    pub id: ItemId,
+    pub list: ListId,
+    pub parent: Option<ItemId>,
+    /// Hex encoded [`SortId`], among the items with the same list and parent
+    pub sort_id: String,
+    pub revision: u64,
+    #[serde(flatten)]
+    pub data: ItemData,
+}
+
+impl ExportItem {
+    pub fn siblings(&self) -> Siblings {
+        match self.parent {
+            Some(parent) => Siblings::Children(parent),
+            None => Siblings::List(self.list),
+        }
+    }
+
+    pub fn decode_sort_id(&self) -> anyhow::Result<SortId> {
+        let bytes =
+            util::hex_decode(&self.sort_id).context(BadRequestError("invalid item sort_id"))?;
+        // empty is valid, it's what the first item of a list gets
+        Ok(SortId::from(bytes))
+    }
+}
+
+impl Export {
+    /// Reject exports that wouldn't import into a consistent database
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.version != EXPORT_VERSION {
+            return Err(BadRequestError("unsupported export version").into());
+        }
+
+        let mut user_ids = HashSet::new();
+        let mut usernames = HashSet::new();
+        for user in &self.users {
+            if !user_ids.insert(user.id) || !usernames.insert(&user.value.username) {
+                return Err(BadRequestError("duplicate user").into());
+            }
+        }
+
+        let mut list_ids = HashSet::new();
+        for list in &self.lists {
+            if !list_ids.insert(list.id) {
+                return Err(BadRequestError("duplicate list").into());
+            }
+            if !list
+                .shares
+                .iter()
+                .all(|share| user_ids.contains(&share.user))
+            {
+                return Err(BadRequestError("list shared with an unknown user").into());
+            }
+        }
+
+        let mut item_lists = HashMap::new();
+        let mut sort_ids = HashSet::new();
+        for item in &self.items {
+            check_item_data(&item.data)?;
+            if !list_ids.contains(&item.list) {
+                return Err(BadRequestError("item in an unknown list").into());
+            }
+            // parents coming first rules out cycles too
+            if let Some(parent) = item.parent {
+                if item_lists.get(&parent) != Some(&item.list) {
+                    return Err(BadRequestError("item parent missing or in another list").into());
+                }
+            }
+            if item_lists.insert(item.id, item.list).is_some() {
+                return Err(BadRequestError("duplicate item").into());
+            }
+            if !sort_ids.insert((item.siblings(), item.decode_sort_id()?)) {
+                return Err(BadRequestError("duplicate item sort_id").into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ListValue;
+    use crate::service::tests::with_temp_db;
+    use crate::service::Service;
+
+    fn item(id: u64, parent: Option<u64>, sort_id: &str) -> ExportItem {
+        ExportItem {
+            id: ItemId(id),
+            list: ListId(1),
+            parent: parent.map(ItemId),
+            sort_id: sort_id.into(),
+            revision: 0,
+            data: ItemData {
+                title: format!("item {id}"),
+                body: String::new(),
+                tags: vec![],
+                completed_at: None,
+                due: None,
+            },
+        }
+    }
+
+    fn export(items: Vec<ExportItem>) -> Export {
+        Export {
+            version: EXPORT_VERSION,
+            users: vec![],
+            lists: vec![ExportList {
+                id: ListId(1),
+                name: "list".into(),
+                shares: vec![],
+            }],
+            items,
+        }
+    }
+
+    #[test]
+    fn check_rejects_inconsistent_items() {
+        assert!(export(vec![item(1, None, "80"), item(2, Some(1), "80")])
+            .check()
+            .is_ok());
+        // same position among siblings
+        assert!(export(vec![item(1, None, "80"), item(2, None, "80")])
+            .check()
+            .is_err());
+        // parent after its sub-item, or a cycle
+        assert!(export(vec![item(2, Some(1), "80"), item(1, Some(2), "80")])
+            .check()
+            .is_err());
+        assert!(export(vec![item(1, None, "8")]).check().is_err());
+        assert!(export(vec![item(1, None, "")]).check().is_ok());
+        let mut tagged = item(1, None, "80");
+        tagged.data.tags = vec!["a,b".into()];
+        assert!(export(vec![tagged]).check().is_err());
+    }
+
+    #[test]
+    fn import_restores_export() -> anyhow::Result<()> {
+        with_temp_db(|opts| {
+            let service = Service::new(opts.clone())?;
+            let user_id = service.create_user("user", "password", true)?;
+            let list_id = service.create_list(
+                user_id,
+                ListValue {
+                    name: "list".into(),
+                },
+            )?;
+            let mut item_ids = vec![];
+            for id in 1..=3 {
+                item_ids.push(service.create_item(user_id, list_id, item(id, None, "").data)?);
+            }
+            // the last created one is in front, make the first a sub-item of it
+            service.change_item_order(user_id, None, item_ids[0], None, None, Some(item_ids[2]))?;
+            let read_items = || -> anyhow::Result<Vec<_>> {
+                Ok(service
+                    .read_items(user_id, list_id)?
+                    .into_iter()
+                    .map(|item| (item.id, item.parent, item.data.title, item.revision))
+                    .collect())
+            };
+            let items = read_items()?;
+
+            let export = service.export_db()?;
+            assert_eq!(export.items.len(), 3);
+            // round trip through JSON, like the `export` and `import` commands
+            let export: Export = serde_json::from_str(&serde_json::to_string(&export)?)?;
+            service.import_db(&export)?;
+            assert_eq!(read_items()?, items);
+            assert!(service.fsck(false)?.is_empty());
+            Ok(())
+        })
+    }
+}
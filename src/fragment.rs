@@ -1,9 +1,23 @@
+use std::collections::{hash_map, HashMap, HashSet};
+
 use maud::{html, Markup, DOCTYPE};
 
-use crate::db::{Item, ItemData, ItemId};
-use crate::service::Service;
+use crate::db::{
+    Access, Item, ItemData, ItemEvent, ItemEventKind, ItemId, ItemValue, ListId, TrashedItem,
+    UserId, UserValue,
+};
+use crate::markdown;
+use crate::service::{Service, Session, CSRF_HEADER};
 
-pub fn page(title: &str, content: Markup) -> Markup {
+/// A full html page, with optional `nav` rendered in the header
+///
+/// With a `session`, htmx requests made from the page carry its CSRF token.
+pub fn page(
+    title: &str,
+    session: Option<&Session>,
+    nav: Option<Markup>,
+    content: Markup,
+) -> Markup {
     /// A basic header with a dynamic `page_title`.
     pub(crate) fn head(page_title: &str) -> Markup {
         html! {
@@ -18,13 +32,16 @@ pub fn page(title: &str, content: Markup) -> Markup {
         }
     }
 
-    pub(crate) fn header() -> Markup {
+    pub(crate) fn header(nav: Option<Markup>) -> Markup {
         html! {
             header ."container py-5 flex flex-row place-content-center gap-6 items-center" {
                     div ."uppercase" { "Much Better Jira" }
                     ."" {
                         img src="/favicon.ico" style="image-rendering: pixelated;" alt="dpc's avatar image";
                     }
+                    @if let Some(nav) = nav {
+                        (nav)
+                    }
             }
         }
     }
@@ -33,6 +50,7 @@ pub fn page(title: &str, content: Markup) -> Markup {
     pub(crate) fn footer() -> Markup {
         html! {
             script src="https://unpkg.com/htmx.org@1.9.4" {};
+            script src="https://unpkg.com/htmx.org@1.9.4/dist/ext/sse.js" {};
             script src="https://unpkg.com/sortablejs@1.15.0/Sortable.min.js" {};
             script type="module" src="/script.js" {};
         }
@@ -40,7 +58,9 @@ pub fn page(title: &str, content: Markup) -> Markup {
 
     html! {
         (head(title))
-        body ."container relative mx-auto !block" style="display: none" {
+        body ."container relative mx-auto !block" style="display: none"
+            hx-headers=[session.map(|session| serde_json::json!({ CSRF_HEADER: session.csrf_token() }).to_string())]
+        {
             div #"gray-out-page" ."fixed inset-0 send-error-hidden"  {
                 div ."relative z-50 bg-white mx-auto max-w-sm p-10 flex flex-center flex-col gap-2" {
                     p { "Connection error" }
@@ -48,7 +68,7 @@ pub fn page(title: &str, content: Markup) -> Markup {
                 }
                 div ."inset-0 absolute z-0 bg-gray-500 opacity-50" {}
             }
-            (header())
+            (header(nav))
 
             main ."container" {
                 (content)
@@ -59,55 +79,448 @@ pub fn page(title: &str, content: Markup) -> Markup {
 }
 
 impl Service {
-    pub fn home_page(&self, item: Option<(ItemId, ItemData)>) -> anyhow::Result<Markup> {
+    /// Header navigation: list switcher and other pages
+    pub fn nav(
+        &self,
+        session: &Session,
+        current_list_id: Option<ListId>,
+    ) -> anyhow::Result<Markup> {
+        let user_id = session.user_id()?;
+        let user = self.load_user(user_id)?;
+        Ok(html! {
+            nav ."flex flex-row gap-3 items-center" {
+                @for (list_id, list, access) in self.read_lists(user_id)? {
+                    @if Some(list_id) == current_list_id {
+                        span ."font-bold" { (list.name) }
+                        @if access == Access::Owner {
+                            a ."hover:underline text-gray-500" href={ "/list/" (list_id) "/share" } { "Share" }
+                        }
+                    } @else if access == Access::Read {
+                        a ."hover:underline" href={ "/list/" (list_id) } { (list.name) }
+                    } @else {
+                        // items dropped here are moved to the front of this list
+                        div ."sortable list-drop" data-list=(list_id) hx-post="/item/order" hx-trigger="changed" hx-swap="none" {
+                            a ."hover:underline" href={ "/list/" (list_id) } { (list.name) }
+                        }
+                    }
+                }
+                form hx-post="/list" {
+                    input ."border shadow-inner shadow-gray-400 rounded p-1 w-24" type="text" name="name" value="" placeholder="New list..." autocomplete="off" {}
+                }
+                form action="/search" method="get" {
+                    input ."border shadow-inner shadow-gray-400 rounded p-1 w-32" type="search" name="q" value="" placeholder="Search..." autocomplete="off" {}
+                }
+                a ."hover:underline" href="/upcoming" { "Upcoming" }
+                a ."hover:underline" href="/trash" { "Trash" }
+                @if user.as_ref().is_some_and(|user| user.is_admin) {
+                    a ."hover:underline" href="/backup" { "Backup" }
+                }
+                @if let Some(user) = user {
+                    span ."text-gray-500" {
+                        (user.username)
+                        @if user.is_admin { " (admin)" }
+                    }
+                    button ."hover:underline" hx-post="/logout" { "Log out" }
+                }
+            }
+        })
+    }
+
+    /// Page of a user without lists, who can still create one in the nav
+    pub fn no_lists_page(&self, session: &Session) -> anyhow::Result<Markup> {
+        Ok(page(
+            "home",
+            Some(session),
+            Some(self.nav(session, None)?),
+            html! {
+                p ."p-1 text-gray-500" { "No lists yet" }
+            },
+        ))
+    }
+
+    /// Page of a list, showing only the items tagged `tag` if given
+    pub fn list_page(
+        &self,
+        session: &Session,
+        list_id: ListId,
+        item: Option<(ItemId, ItemValue)>,
+        tag: Option<&str>,
+    ) -> anyhow::Result<Markup> {
+        let user_id = session.user_id()?;
+        let (_, access) = self.load_list(user_id, list_id)?;
+        let editable = Access::Edit <= access;
         Ok(page(
             "home",
+            Some(session),
+            Some(self.nav(session, Some(list_id))?),
+            html! {
+                // requests made from a filtered view carry the tag, to re-render it filtered
+                div hx-vals=[tag.map(|tag| serde_json::json!({ "tag": tag }).to_string())] {
+                    @if let Some(tag) = tag {
+                        div ."p-1" {
+                            "Tagged " span ."tag" { (tag) } " "
+                            a ."hover:underline text-gray-500" href=(list_url(list_id, None)) { "Show all" }
+                        }
+                    }
+                    div ."container flex flex-col md:flex-row" {
+                        (item_edit_form(item, editable, None))
+                        // reload the items or swap in changed rows as others edit the list
+                        div ."container shrink grow p-1" hx-ext="sse" sse-connect={ "/list/" (list_id) "/events" } {
+                            div ."hidden" hx-get={ "/list/" (list_id) "/items" } hx-trigger="sse:list" hx-target="#items" hx-swap="outerHTML" {}
+                            // completed items are only hidden, so they keep their place in the list
+                            button ."hide-completed-toggle px-2 m-1 shadow-md rounded-md" type="button" {
+                                span ."hide-completed-only" { "Show completed" }
+                                span ."hide-completed-hidden" { "Hide completed" }
+                            }
+                            (self.list_items_form(user_id, list_id, tag)?)
+                        }
+                    }
+                    (undo_toast(None, false))
+                }
+            },
+        ))
+    }
+
+    /// [`Item::items_form`] of `list_id`, with only the items tagged `tag` if
+    /// given
+    pub fn list_items_form(
+        &self,
+        user_id: UserId,
+        list_id: ListId,
+        tag: Option<&str>,
+    ) -> anyhow::Result<Markup> {
+        let (_, access) = self.load_list(user_id, list_id)?;
+        let items = match tag {
+            Some(tag) => self.read_tagged_items(user_id, list_id, tag)?,
+            None => self.read_items(user_id, list_id)?,
+        };
+        Ok(Item::items_form(
+            "items",
+            list_id,
+            Access::Edit <= access,
+            &items,
+        ))
+    }
+
+    pub fn list_share_page(&self, session: &Session, list_id: ListId) -> anyhow::Result<Markup> {
+        let shares = self.read_list_shares(session.user_id()?, list_id)?;
+        Ok(page(
+            "share",
+            Some(session),
+            Some(self.nav(session, Some(list_id))?),
+            html! {
+                div ."container p-1 flex flex-col gap-2" {
+                    form ."flex gap-1" hx-post={ "/list/" (list_id) "/share" } hx-target="#list-shares" hx-swap="outerHTML" {
+                        input ."border shadow-inner shadow-gray-400 rounded p-1" type="text" name="username" placeholder="Username" autocomplete="off";
+                        select ."border rounded p-1" name="access" {
+                            option value="read" { "Read only" }
+                            option value="edit" { "Edit" }
+                            option value="none" { "No access" }
+                        }
+                        button ."px-2 py-1 shadow-md bg-primary-btn text-custom-white rounded-md" { "Share" }
+                    }
+                    (list_shares(list_id, &shares))
+                }
+            },
+        ))
+    }
+
+    pub fn trash_page(&self, session: &Session) -> anyhow::Result<Markup> {
+        Ok(page(
+            "trash",
+            Some(session),
+            Some(self.nav(session, None)?),
+            html! {
+                div ."container p-1" {
+                    (trash_list(&self.read_trash(session.user_id()?)?))
+                }
+            },
+        ))
+    }
+
+    /// Download of the JSON export and upload of one to import
+    pub fn backup_page(&self, session: &Session) -> anyhow::Result<Markup> {
+        Ok(page(
+            "backup",
+            Some(session),
+            Some(self.nav(session, None)?),
+            html! {
+                div ."container p-1 flex flex-col gap-2" {
+                    div {
+                        a ."px-2 py-1 shadow-md bg-primary-btn text-custom-white rounded-md" href="/backup/export" download { "Download export" }
+                    }
+                    form ."flex gap-2 items-center" hx-post="/backup/import" hx-confirm="Replace all users, lists and items with the ones in this file?" {
+                        input type="file" accept=".json,application/json" data-import-file;
+                        input type="hidden" name="data";
+                        button ."px-2 py-1 shadow-md bg-red-700 text-custom-white rounded-md" { "Import" }
+                    }
+                    p ."text-gray-500" { "Importing replaces everything and logs everyone out. Item history and the trash are not part of the export." }
+                }
+            },
+        ))
+    }
+
+    pub fn search_page(
+        &self,
+        session: &Session,
+        query: &str,
+        items: &[Item],
+    ) -> anyhow::Result<Markup> {
+        let editable_list_ids = self.read_editable_list_ids(session.user_id()?)?;
+        Ok(page(
+            "search",
+            Some(session),
+            Some(self.nav(session, None)?),
             html! {
                 div ."container flex flex-col md:flex-row" {
-                    (item_edit_form(item, None))
+                    (item_edit_form(None, false, None))
                     div ."container shrink grow p-1" {
-                        (Item::items_form("items", &self.read_items()?))
+                        input ."border shadow-inner shadow-gray-400 rounded my-1 p-1 w-full" type="search" name="q" value=(query) placeholder="Search..." autocomplete="off" autofocus
+                            hx-get="/search" hx-trigger="input changed delay:300ms, search" hx-target="#items" hx-swap="outerHTML" hx-push-url="true" {}
+                        (search_results(query, items, &editable_list_ids))
                     }
                 }
             },
         ))
     }
+
+    /// Items with a due date, soonest first and not draggable, as the order
+    /// isn't theirs
+    pub fn upcoming_page(&self, session: &Session) -> anyhow::Result<Markup> {
+        let items = self.read_upcoming_items(session.user_id()?)?;
+        let editable_list_ids = self.read_editable_list_ids(session.user_id()?)?;
+        Ok(page(
+            "upcoming",
+            Some(session),
+            Some(self.nav(session, None)?),
+            html! {
+                div ."container flex flex-col md:flex-row" {
+                    (item_edit_form(None, false, None))
+                    div ."container shrink grow p-1" {
+                        div #items ."no-drag border-1 border-solid rounded-sm divide-y divide-solid shadow shadow-black" {
+                            @for item in &items {
+                                (item.items_flat_row(editable_list_ids.contains(&item.list_id)))
+                            }
+                            @if items.is_empty() {
+                                p ."p-1 text-gray-500" { "No items with a due date" }
+                            }
+                        }
+                    }
+                }
+            },
+        ))
+    }
+
+    pub fn item_history_page(&self, session: &Session, item_id: ItemId) -> anyhow::Result<Markup> {
+        let events = self.read_item_history(session.user_id()?, item_id)?;
+        let mut usernames = HashMap::new();
+        for event in &events {
+            if let hash_map::Entry::Vacant(entry) = usernames.entry(event.actor) {
+                entry.insert(
+                    self.load_user(event.actor)?
+                        .map(|user| user.username)
+                        .unwrap_or_else(|| event.actor.to_string()),
+                );
+            }
+        }
+        Ok(page(
+            "history",
+            Some(session),
+            Some(self.nav(session, None)?),
+            html! {
+                div ."container p-1" {
+                    (item_history(&events, &usernames))
+                }
+            },
+        ))
+    }
+}
+
+pub fn list_shares(list_id: ListId, shares: &[(UserId, UserValue, Access)]) -> Markup {
+    html! {
+        div #list-shares ."border-1 border-solid rounded-sm divide-y divide-solid shadow shadow-black" {
+            @for (_, user, access) in shares {
+                div ."container p-1 even:bg-shade-02 flex justify-between items-center gap-1" {
+                    span ."w-full" { (user.username) }
+                    span ."whitespace-nowrap text-gray-500" {
+                        @match access {
+                            Access::Read => "read only",
+                            Access::Edit => "edit",
+                            Access::Owner => "owner",
+                        }
+                    }
+                    @if *access != Access::Owner {
+                        button ."px-2 py-1 shadow-md bg-red-700 text-custom-white rounded-md"
+                            hx-post={ "/list/" (list_id) "/share" }
+                            hx-vals=(serde_json::json!({ "username": user.username, "access": "none" }))
+                            hx-target="#list-shares"
+                            hx-swap="outerHTML"
+                        { "Remove" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Login page, for the anonymous `session`
+pub fn login_page(session: &Session, has_users: bool) -> Markup {
+    page("login", Some(session), None, login_form(None, has_users))
+}
+
+/// Login form, with an optional `error` from the previous attempt
+pub fn login_form(error: Option<&str>, has_users: bool) -> Markup {
+    html! {
+        form ."container max-w-sm mx-auto p-1 flex flex-col gap-1" hx-post="/login" hx-swap="outerHTML" {
+            @if let Some(error) = error {
+                p ."text-red-700" { (error) }
+            }
+            @if !has_users {
+                p ."text-gray-500" { "No users yet. Create one with `htmx-sorta create-user --admin <USERNAME>`." }
+            }
+            input ."border shadow-inner shadow-gray-400 rounded py-1 px-2" type="text" name="username" placeholder="Username" autocomplete="username" autofocus;
+            input ."border shadow-inner shadow-gray-400 rounded py-1 px-2" type="password" name="password" placeholder="Password" autocomplete="current-password";
+            button ."px-2 py-1 shadow-md bg-primary-btn text-custom-white rounded-md" { "Log in" }
+        }
+    }
 }
 
 impl Item {
-    pub fn items_form(dom_id: &str, items: &[Item]) -> Markup {
+    /// Items of a list, sortable and with a new item form if `editable`
+    pub fn items_form(dom_id: &str, list_id: ListId, editable: bool, items: &[Item]) -> Markup {
         html! {
-            div #(dom_id) ."sortable border-1 border-solid rounded-sm divide-y divide-solid shadow shadow-black"  hx-post="/item/order" hx-trigger="changed" hx-swap="none" {
+            div #(dom_id) .sortable[editable] ."border-1 border-solid rounded-sm divide-y divide-solid shadow shadow-black" data-list=(list_id) hx-post="/item/order" hx-trigger="changed" hx-swap="none" {
 
-                form ."items-new flex" hx-post="/item" hx-target="closest div" hx-swap="outerHTML" hx-indicator={"#item-edit, #"(dom_id)} {
-                    input ."border shadow-inner shadow-gray-400 rounded m-1 p-1 rounded w-full" type="text" name="title" value="" placeholder="New..." autocomplete="off" {}
-                    input ."hidden" type="submit" {}
+                @if editable {
+                    form ."items-new flex" hx-post={ "/list/" (list_id) "/item" } hx-target="closest div" hx-swap="outerHTML" hx-indicator={"#item-edit, #"(dom_id)} {
+                        input ."border shadow-inner shadow-gray-400 rounded m-1 p-1 rounded w-full" type="text" name="title" value="" placeholder="New..." autocomplete="off" {}
+                        input ."hidden" type="submit" {}
+                        // multi-select mode: check items, then drag them or move them all at once
+                        button ."multi-select-only hidden px-2 m-1 shadow-md bg-primary-btn text-custom-white rounded-md" type="button" data-batch-move="top" { "Top" }
+                        button ."multi-select-only hidden px-2 m-1 shadow-md bg-primary-btn text-custom-white rounded-md" type="button" data-batch-move="bottom" { "Bottom" }
+                        button ."multi-select-toggle px-2 m-1 shadow-md rounded-md" type="button" { "Select" }
+                    }
                 }
 
-                @for item in items {
-                    (item.items_sortable_row())
+                @let children = Item::children_by_parent(items);
+                @for item in children.get(&None).into_iter().flatten() {
+                    (item.items_tree_row(list_id, editable, &children))
+                }
+            }
+        }
+    }
+
+    /// `items` grouped by parent, in order, with items whose parent isn't
+    /// among them at the top level
+    fn children_by_parent(items: &[Item]) -> HashMap<Option<ItemId>, Vec<&Item>> {
+        let ids: HashSet<_> = items.iter().map(|item| item.id).collect();
+        let mut children: HashMap<_, Vec<_>> = HashMap::new();
+        for item in items {
+            let parent = item.parent.filter(|parent| ids.contains(parent));
+            children.entry(parent).or_default().push(item);
+        }
+        children
+    }
+
+    /// Row with the sub-items of the item nested in their own sortable
+    fn items_tree_row(
+        &self,
+        list_id: ListId,
+        editable: bool,
+        children: &HashMap<Option<ItemId>, Vec<&Item>>,
+    ) -> Markup {
+        html! {
+            div."draggable container even:bg-shade-02" #{ (self.id) } {
+                (self.item_line(editable))
+                div ."item-children" .sortable[editable] data-list=(list_id) data-parent=(self.id) hx-post="/item/order" hx-trigger="changed" hx-swap="none" {
+                    @for child in children.get(&Some(self.id)).into_iter().flatten() {
+                        (child.items_tree_row(list_id, editable, children))
+                    }
                 }
             }
         }
     }
 
-    pub fn items_sortable_row(&self) -> Markup {
+    /// Row without sub-items that can't be dragged, for flat views like
+    /// search results
+    pub fn items_flat_row(&self, editable: bool) -> Markup {
         html! {
-            div."draggable container p-1 even:bg-shade-02 group flex justify-between gap-1" #{ (self.id) } {
+            div."container even:bg-shade-02" #{ (self.id) } {
+                (self.item_line(editable))
+            }
+        }
+    }
+
+    /// The item itself within its row, replaced on changes
+    ///
+    /// It can only be marked done if `editable`.
+    pub fn item_line(&self, editable: bool) -> Markup {
+        let completed = self.data.completed_at.is_some();
+        let overdue = !completed
+            && self
+                .data
+                .due
+                .as_ref()
+                .is_some_and(|due| *due < crate::util::today());
+        html! {
+            div."item-line p-1 group flex justify-between gap-1" .completed[completed] sse-swap=(self.id) hx-swap="outerHTML" {
                 div .handle { "<>" };
-                div ."w-full" hx-trigger="click" hx-get={ "/item/" (self.id) } hx-push-url="true" hx-select="#item-edit" hx-target="#item-edit" hx-indicator="#item-edit" hx-swap="outerHTML" {
-                     span ."group-hover:underline" {
+                input ."item-select hidden" type="checkbox";
+                input type="checkbox" checked[completed] disabled[!editable] title="Done"
+                    hx-post={ "/item/" (self.id) "/complete" }
+                    hx-vals=(serde_json::json!({ "completed": !completed }))
+                    hx-target="closest .item-line";
+                div ."w-full" hx-trigger="click" hx-get={ "/list/" (self.list_id) "/item/" (self.id) } hx-push-url="true" hx-target="#item-edit" hx-indicator="#item-edit" hx-swap="outerHTML" {
+                     span ."group-hover:underline" .line-through[completed] .text-gray-500[completed] {
                          (self.data.title)
                      }
                 }
+                @for tag in &self.data.tags {
+                    a ."tag whitespace-nowrap hover:underline" href=(list_url(self.list_id, Some(tag))) { (tag) }
+                }
+                @if let Some(due) = &self.data.due {
+                    span ."whitespace-nowrap" .text-gray-500[!overdue] ."text-red-700 font-bold"[overdue] title="Due" { (due) }
+                }
+            }
+        }
+    }
+}
+
+/// Toast offering to undo the change described by `message`, empty without
+/// one
+pub fn undo_toast(message: Option<&str>, oob: bool) -> Markup {
+    html! {
+        div #toast ."toast" hx-swap-oob=[oob.then_some("true")] {
+            @if let Some(message) = message {
+                div ."px-3 py-2 flex gap-3 items-center shadow-md bg-white rounded-md border-1 border-solid" {
+                    span { (message) }
+                    button ."px-2 py-1 shadow-md bg-primary-btn text-custom-white rounded-md" hx-post="/undo" hx-target="#items" hx-swap="outerHTML" { "Undo" }
+                    button ."text-gray-500" type="button" data-dismiss-toast { "×" }
+                }
             }
         }
     }
 }
 
-pub fn item_edit_form(item: Option<(ItemId, ItemData)>, hx_swap_oob_id: Option<&str>) -> Markup {
+/// Url of the page of `list_id`, filtered by `tag` if given
+pub fn list_url(list_id: ListId, tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) => format!(
+            "/list/{list_id}?{}",
+            serde_urlencoded::to_string([("tag", tag)]).expect("can't fail")
+        ),
+        None => format!("/list/{list_id}"),
+    }
+}
+
+/// Item edit form, read only unless `editable`
+pub fn item_edit_form(
+    item: Option<(ItemId, ItemValue)>,
+    editable: bool,
+    hx_swap_oob_id: Option<&str>,
+) -> Markup {
     html! {
-        @if let Some((item_id, item_data)) = item {
+        @if let Some((item_id, item)) = item {
             form
                 id=@if let Some(oob) = hx_swap_oob_id {
                     (oob)
@@ -116,18 +529,185 @@ pub fn item_edit_form(item: Option<(ItemId, ItemData)>, hx_swap_oob_id: Option<&
                 }
                 ."container p-1"
                 hx-post={ "/item/" (item_id) }
-                hx-trigger="submit, click from:find button, keydown[ctrlKey && keyCode==13]"
+                hx-trigger="submit, click from:find .item-save, keydown[ctrlKey && keyCode==13]"
                 hx-target="#items"
-                hx-select="#items"
                 hx-swap="outerHTML"
                 hx-swap-oob=@if hx_swap_oob_id.is_some() { "outerHTML" }
             {
-                input type="text" name="title" autofocus placeholder="Title..." ."border shadow-inner shadow-gray-400 rounded my-1 py-1 px-2 w-full" value=(item_data.title);
-                textarea  name="body" placeholder="Body..."  ."border shadow-inner shadow-gray-400 rounded my-1 py-1 px-2 h-24 w-full" { (item_data.body) }
-                button ."px-2 py-1 my-1 shadow-md bg-primary-btn text-custom-white rounded-md" { "Save" }
+                input type="hidden" name="revision" value=(item.revision);
+                input type="text" name="title" autofocus placeholder="Title..." ."border shadow-inner shadow-gray-400 rounded my-1 py-1 px-2 w-full" value=(item.data.title) readonly[!editable];
+                (item_body_field(&item.data.body, editable, !editable))
+                input type="text" name="tags" placeholder="Tags, comma separated..." ."border shadow-inner shadow-gray-400 rounded my-1 py-1 px-2 w-full" value=(item.data.tags.join(", ")) readonly[!editable];
+                label ."text-gray-500" {
+                    "Due "
+                    input type="date" name="due" ."border shadow-inner shadow-gray-400 rounded my-1 py-1 px-2" value=[item.data.due.as_ref()] readonly[!editable];
+                }
+                @if editable {
+                    button ."item-save px-2 py-1 my-1 shadow-md bg-primary-btn text-custom-white rounded-md" { "Save" }
+                    button type="button" ."px-2 py-1 my-1 shadow-md bg-red-700 text-custom-white rounded-md"
+                        hx-post={ "/item/" (item_id) "/delete" }
+                    { "Delete" }
+                }
+                a ."px-2 hover:underline text-gray-500" href={ "/item/" (item_id) "/history" } { "History" }
             }
         } @else {
-            form #item-edit ."container hidden" {
+            form #item-edit ."container hidden" hx-swap-oob=@if hx_swap_oob_id.is_some() { "outerHTML" } {
+            }
+        }
+    }
+}
+
+/// Body of [`item_edit_form`], either in the editor or rendered from
+/// Markdown
+///
+/// The preview keeps the body in a hidden field, so the form can still be
+/// saved from it.
+pub fn item_body_field(body: &str, editable: bool, preview: bool) -> Markup {
+    html! {
+        div ."item-body" {
+            @if preview {
+                input type="hidden" name="body" value=(body);
+                div ."markdown border rounded my-1 py-1 px-2 w-full" { (markdown::render(body)) }
+            } @else {
+                textarea name="body" placeholder="Body..." ."border shadow-inner shadow-gray-400 rounded my-1 py-1 px-2 h-24 w-full" { (body) }
+            }
+            @if editable {
+                button type="button" ."px-2 text-gray-500 hover:underline"
+                    hx-post="/item/preview"
+                    hx-vals=(serde_json::json!({ "preview": !preview }))
+                    hx-target="closest .item-body"
+                    hx-swap="outerHTML"
+                { @if preview { "Edit" } @else { "Preview" } }
+            }
+        }
+    }
+}
+
+/// Replaces [`item_edit_form`] when saving `mine` conflicted with a change
+/// to the item, resulting in `current`
+pub fn item_conflict_form(item_id: ItemId, current: &ItemValue, mine: &ItemData) -> Markup {
+    html! {
+        form #item-edit ."container p-1 flex flex-col gap-1"
+            hx-post={ "/item/" (item_id) }
+            hx-target="#items"
+            hx-swap="outerHTML"
+        {
+            p ."text-red-700" { "This item was changed by someone else while you were editing it." }
+            // keeping mine overwrites the current revision
+            input type="hidden" name="revision" value=(current.revision);
+            input type="hidden" name="title" value=(mine.title);
+            input type="hidden" name="body" value=(mine.body);
+            input type="hidden" name="tags" value=(mine.tags.join(", "));
+            input type="hidden" name="due" value=[mine.due.as_ref()];
+            div ."flex flex-col md:flex-row gap-2" {
+                div ."container p-1 border-1 border-solid rounded-sm" {
+                    h3 ."font-bold" { "Theirs" }
+                    p { (current.data.title) }
+                    p ."whitespace-pre-wrap" { (current.data.body) }
+                    p ."text-gray-500" { (current.data.tags.join(", ")) }
+                    @if let Some(due) = &current.data.due { p ."text-gray-500" { "Due " (due) } }
+                }
+                div ."container p-1 border-1 border-solid rounded-sm" {
+                    h3 ."font-bold" { "Yours" }
+                    p { (mine.title) }
+                    p ."whitespace-pre-wrap" { (mine.body) }
+                    p ."text-gray-500" { (mine.tags.join(", ")) }
+                    @if let Some(due) = &mine.due { p ."text-gray-500" { "Due " (due) } }
+                }
+            }
+            div ."flex gap-1" {
+                button type="button" ."px-2 py-1 shadow-md rounded-md"
+                    hx-get={ "/item/" (item_id) "/edit" }
+                    hx-target="#item-edit"
+                { "Keep theirs" }
+                button ."px-2 py-1 shadow-md bg-primary-btn text-custom-white rounded-md" { "Keep yours" }
+            }
+        }
+    }
+}
+
+/// Items matching a search, best matches first
+pub fn search_results(query: &str, items: &[Item], editable_list_ids: &HashSet<ListId>) -> Markup {
+    html! {
+        div #items ."no-drag border-1 border-solid rounded-sm divide-y divide-solid shadow shadow-black" {
+            @for item in items {
+                (item.items_flat_row(editable_list_ids.contains(&item.list_id)))
+            }
+            @if items.is_empty() && !query.trim().is_empty() {
+                p ."p-1 text-gray-500" { "No matching items" }
+            }
+        }
+    }
+}
+
+pub fn trash_list(items: &[(ItemId, TrashedItem)]) -> Markup {
+    let now = crate::util::now_unix_secs();
+    html! {
+        div #trash ."border-1 border-solid rounded-sm divide-y divide-solid shadow shadow-black" hx-target="#trash" hx-swap="outerHTML" {
+            @if items.is_empty() {
+                p ."p-1" { "Trash is empty." }
+            }
+            @for (item_id, trashed) in items {
+                div ."container p-1 even:bg-shade-02 flex justify-between items-center gap-1" {
+                    span ."w-full" { (trashed.value.data.title) }
+                    span ."whitespace-nowrap text-gray-500" {
+                        "deleted " (now.saturating_sub(trashed.deleted_at) / 60) " min ago"
+                    }
+                    button ."px-2 py-1 shadow-md bg-primary-btn text-custom-white rounded-md" hx-post={ "/trash/" (item_id) "/restore" } { "Restore" }
+                    button ."px-2 py-1 shadow-md bg-red-700 text-custom-white rounded-md" hx-post={ "/trash/" (item_id) "/purge" } hx-confirm="Delete this item permanently?" { "Purge" }
+                }
+            }
+        }
+    }
+}
+
+/// Changes of an item, most recent first
+pub fn item_history(events: &[ItemEvent], usernames: &HashMap<UserId, String>) -> Markup {
+    let now = crate::util::now_unix_secs();
+    html! {
+        div ."border-1 border-solid rounded-sm divide-y divide-solid shadow shadow-black" {
+            @for event in events.iter().rev() {
+                div ."container p-1 even:bg-shade-02 flex flex-col gap-1" {
+                    div ."flex justify-between gap-1" {
+                        span ."font-bold" {
+                            @match event.kind {
+                                ItemEventKind::Create => "Created",
+                                ItemEventKind::Update => "Edited",
+                                ItemEventKind::Reorder { ref from, ref to } if from.0 != to.0 => "Moved to another list",
+                                ItemEventKind::Reorder { ref from, ref to } if from.1 != to.1 => "Moved under another item",
+                                ItemEventKind::Reorder { .. } => "Reordered",
+                                ItemEventKind::Delete => "Deleted",
+                                ItemEventKind::Restore => "Restored",
+                            }
+                        }
+                        span ."whitespace-nowrap text-gray-500" {
+                            (usernames.get(&event.actor).map(String::as_str).unwrap_or_default())
+                            ", " (now.saturating_sub(event.at) / 60) " min ago"
+                        }
+                    }
+                    @if let ItemEventKind::Update = event.kind {
+                        @if let (Some(before), Some(after)) = (&event.before, &event.after) {
+                            @if before.title != after.title {
+                                p { span ."line-through text-gray-500" { (before.title) } " " (after.title) }
+                            }
+                            @if before.body != after.body {
+                                p ."whitespace-pre-wrap line-through text-gray-500" { (before.body) }
+                                p ."whitespace-pre-wrap" { (after.body) }
+                            }
+                            @if before.completed_at != after.completed_at {
+                                p { @if after.completed_at.is_some() { "Marked done" } @else { "Marked not done" } }
+                            }
+                            @if before.due != after.due {
+                                p { "Due " span ."line-through text-gray-500" { (before.due.as_deref().unwrap_or_default()) } " " (after.due.as_deref().unwrap_or("none")) }
+                            }
+                            @if before.tags != after.tags {
+                                p { span ."line-through text-gray-500" { (before.tags.join(", ")) } " " (after.tags.join(", ")) }
+                            }
+                        }
+                    } @else if let Some(data) = event.after.as_ref().or(event.before.as_ref()) {
+                        p { (data.title) }
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,233 @@
+//! Finding inconsistencies between [`ITEM_TABLE`] and the order tables
+//!
+//! The items are the source of truth, the order tables can be rebuilt from
+//! them.
+//!
+//! [`ITEM_TABLE`]: crate::db::ITEM_TABLE
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::db::{ItemId, ItemValue, ListId, Siblings};
+use crate::sortid::SortId;
+use crate::util;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// An order entry for an item that doesn't exist
+    OrphanedEntry { siblings: Siblings, item_id: ItemId },
+    /// An item without any order entry
+    MissingEntry { item_id: ItemId },
+    /// An order entry among other siblings, or with another `SortId`, than
+    /// its item
+    MismatchedEntry {
+        siblings: Siblings,
+        sort_id: SortId,
+        item_id: ItemId,
+    },
+    /// Items sharing a `SortId` among their siblings
+    DuplicateSortId {
+        siblings: Siblings,
+        item_ids: Vec<ItemId>,
+    },
+    /// An item whose parent is gone, in another list, or its own ancestor
+    BadParent { item_id: ItemId },
+    /// An item in a list that doesn't exist
+    MissingList { item_id: ItemId, list_id: ListId },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::OrphanedEntry { siblings, item_id } => {
+                write!(f, "{siblings}: order entry of missing item {item_id}")
+            }
+            Problem::MissingEntry { item_id } => write!(f, "{item_id}: no order entry"),
+            Problem::MismatchedEntry {
+                siblings,
+                sort_id,
+                item_id,
+            } => write!(
+                f,
+                "{siblings}: order entry {} doesn't match item {item_id}",
+                util::hex_encode(sort_id.as_bytes())
+            ),
+            Problem::DuplicateSortId { siblings, item_ids } => {
+                write!(f, "{siblings}: items sharing a sort id:")?;
+                for item_id in item_ids {
+                    write!(f, " {item_id}")?;
+                }
+                Ok(())
+            }
+            Problem::BadParent { item_id } => write!(f, "{item_id}: invalid parent"),
+            Problem::MissingList { item_id, list_id } => {
+                write!(f, "{item_id}: in missing list {list_id}")
+            }
+        }
+    }
+}
+
+/// Compare `items` with the `entries` of the order tables
+pub fn find_problems(
+    list_ids: &HashSet<ListId>,
+    items: &BTreeMap<ItemId, ItemValue>,
+    entries: &[(Siblings, SortId, ItemId)],
+) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    for (item_id, item) in items {
+        if !list_ids.contains(&item.list_id) {
+            problems.push(Problem::MissingList {
+                item_id: *item_id,
+                list_id: item.list_id,
+            });
+        }
+        if has_bad_parent(items, *item_id) {
+            problems.push(Problem::BadParent { item_id: *item_id });
+        }
+    }
+
+    let mut entry_item_ids = HashSet::new();
+    for (siblings, sort_id, item_id) in entries {
+        entry_item_ids.insert(*item_id);
+        match items.get(item_id) {
+            None => problems.push(Problem::OrphanedEntry {
+                siblings: *siblings,
+                item_id: *item_id,
+            }),
+            Some(item) if item.siblings() != *siblings || item.sort_id != *sort_id => problems
+                .push(Problem::MismatchedEntry {
+                    siblings: *siblings,
+                    sort_id: sort_id.clone(),
+                    item_id: *item_id,
+                }),
+            Some(_) => {}
+        }
+    }
+    for item_id in items.keys() {
+        if !entry_item_ids.contains(item_id) {
+            problems.push(Problem::MissingEntry { item_id: *item_id });
+        }
+    }
+
+    for (siblings, item_ids) in duplicate_sort_ids(items) {
+        problems.push(Problem::DuplicateSortId { siblings, item_ids });
+    }
+
+    problems
+}
+
+/// Items sharing their `SortId` with a sibling, by keyspace
+pub fn duplicate_sort_ids(items: &BTreeMap<ItemId, ItemValue>) -> Vec<(Siblings, Vec<ItemId>)> {
+    let mut by_position: HashMap<(Siblings, &SortId), Vec<ItemId>> = HashMap::new();
+    for (item_id, item) in items {
+        by_position
+            .entry((item.siblings(), &item.sort_id))
+            .or_default()
+            .push(*item_id);
+    }
+    let mut duplicates: Vec<_> = by_position
+        .into_iter()
+        .filter(|(_, item_ids)| 1 < item_ids.len())
+        .map(|((siblings, _), item_ids)| (siblings, item_ids))
+        .collect();
+    duplicates.sort_by_key(|(_, item_ids)| item_ids[0]);
+    duplicates
+}
+
+/// Whether the parent of `item_id` is missing, in another list, or a
+/// descendant of it
+pub fn has_bad_parent(items: &BTreeMap<ItemId, ItemValue>, item_id: ItemId) -> bool {
+    let item = &items[&item_id];
+    let mut seen = HashSet::new();
+    let mut ancestor_id = item.parent;
+    while let Some(id) = ancestor_id {
+        if id == item_id {
+            return true;
+        }
+        let Some(ancestor) = items.get(&id) else {
+            return ancestor_id == item.parent;
+        };
+        if ancestor_id == item.parent && ancestor.list_id != item.list_id {
+            return true;
+        }
+        if !seen.insert(id) {
+            // a loop further up, reported for the items in it
+            return false;
+        }
+        ancestor_id = ancestor.parent;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ItemData;
+
+    fn item(parent: Option<u64>, sort_id: u8) -> ItemValue {
+        ItemValue {
+            list_id: ListId(1),
+            sort_id: SortId::from(vec![sort_id]),
+            data: ItemData {
+                title: String::new(),
+                body: String::new(),
+                tags: vec![],
+                completed_at: None,
+                due: None,
+            },
+            revision: 0,
+            parent: parent.map(ItemId),
+        }
+    }
+
+    #[test]
+    fn finds_problems() {
+        let items = BTreeMap::from([
+            (ItemId(1), item(None, 0x40)),
+            (ItemId(2), item(None, 0x40)),
+            (ItemId(3), item(Some(4), 0x80)),
+            (ItemId(4), item(Some(3), 0x80)),
+            (ItemId(5), item(Some(1), 0x80)),
+        ]);
+        let list = Siblings::List(ListId(1));
+        let entries = [
+            (list, SortId::from(vec![0x40]), ItemId(1)),
+            (list, SortId::from(vec![0x80]), ItemId(2)),
+            (list, SortId::from(vec![0x80]), ItemId(9)),
+            (
+                Siblings::Children(ItemId(4)),
+                SortId::from(vec![0x80]),
+                ItemId(3),
+            ),
+            (
+                Siblings::Children(ItemId(3)),
+                SortId::from(vec![0x80]),
+                ItemId(4),
+            ),
+        ];
+
+        let problems = find_problems(&HashSet::from([ListId(1)]), &items, &entries);
+        assert_eq!(
+            problems,
+            vec![
+                Problem::BadParent { item_id: ItemId(3) },
+                Problem::BadParent { item_id: ItemId(4) },
+                Problem::MismatchedEntry {
+                    siblings: list,
+                    sort_id: SortId::from(vec![0x80]),
+                    item_id: ItemId(2),
+                },
+                Problem::OrphanedEntry {
+                    siblings: list,
+                    item_id: ItemId(9),
+                },
+                Problem::MissingEntry { item_id: ItemId(5) },
+                Problem::DuplicateSortId {
+                    siblings: list,
+                    item_ids: vec![ItemId(1), ItemId(2)],
+                },
+            ]
+        );
+    }
+}
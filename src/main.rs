@@ -1,16 +1,27 @@
+mod api;
 mod db;
+mod export;
 mod fragment;
+mod fsck;
+mod markdown;
+mod migrate;
 mod opts;
 mod rate_limit;
 mod response;
 mod routes;
+mod search;
 mod service;
 mod sortid;
+mod sse;
 mod util;
 
-use anyhow::Context;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{bail, Context};
 use clap::Parser;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
 use crate::service::Service;
@@ -24,7 +35,21 @@ fn main() -> anyhow::Result<()> {
 
     let opts = opts::Opts::parse();
 
-    let service = Service::new(opts.clone())?;
+    match opts.command.clone().unwrap_or(opts::Command::Serve) {
+        opts::Command::Serve => serve(opts),
+        opts::Command::CreateUser { username, admin } => {
+            create_user(&Service::new(opts)?, &username, admin)
+        }
+        opts::Command::Export { output } => export(&Service::open(opts)?, output.as_deref()),
+        opts::Command::Import { input } => import(&Service::new(opts)?, &input),
+        opts::Command::Check { repair } => check(Service::open(opts)?, repair),
+        opts::Command::Rebalance => rebalance(&Service::new(opts)?),
+        opts::Command::Compact => compact(Service::new(opts)?),
+    }
+}
+
+fn serve(opts: opts::Opts) -> anyhow::Result<()> {
+    let service = Service::new(opts.clone())?.start_session_purge_thread();
 
     let server = astra::Server::bind(opts.listen);
 
@@ -36,6 +61,83 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+fn create_user(service: &Service, username: &str, is_admin: bool) -> anyhow::Result<()> {
+    eprintln!("Password for {username}:");
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .context("Failed to read password")?;
+    let password = password.trim_end_matches(['\r', '\n']);
+
+    let user_id = service.create_user(username, password, is_admin)?;
+    info!(%user_id, %username, is_admin, "Created user");
+
+    Ok(())
+}
+
+fn export(service: &Service, output: Option<&Path>) -> anyhow::Result<()> {
+    let export = service.export_db()?;
+    match output {
+        Some(path) => serde_json::to_writer_pretty(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+            &export,
+        )?,
+        None => serde_json::to_writer_pretty(std::io::stdout().lock(), &export)?,
+    }
+    info!(items = export.items.len(), "Exported database");
+
+    Ok(())
+}
+
+fn import(service: &Service, input: &Path) -> anyhow::Result<()> {
+    let file = File::open(input).with_context(|| format!("Failed to open {}", input.display()))?;
+    let export = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to parse {}", input.display()))?;
+    service.import_db(&export)
+}
+
+fn check(mut service: Service, repair: bool) -> anyhow::Result<()> {
+    // redb rewrites the file even when it's fine
+    if repair {
+        if service.check_integrity()? {
+            info!("Database file is fine");
+        } else {
+            warn!("Database file was damaged and got repaired");
+        }
+    }
+
+    let problems = service.fsck(repair)?;
+    for problem in &problems {
+        println!("{problem}");
+    }
+    if problems.is_empty() {
+        info!("Items and their order are consistent");
+    } else if repair {
+        info!(count = problems.len(), "Repaired problems");
+    } else {
+        bail!(
+            "Found {} problems, run with --repair to fix them",
+            problems.len()
+        );
+    }
+
+    Ok(())
+}
+
+fn rebalance(service: &Service) -> anyhow::Result<()> {
+    let count = service.rebalance_all()?;
+    info!(count, "Rebalanced lists");
+
+    Ok(())
+}
+
+fn compact(mut service: Service) -> anyhow::Result<()> {
+    service.compact()?;
+    info!("Compacted database file");
+
+    Ok(())
+}
+
 fn init_logging() -> anyhow::Result<()> {
     let subscriber = tracing_subscriber::fmt()
         .with_writer(std::io::stderr) // Print to stderr
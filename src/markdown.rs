@@ -0,0 +1,183 @@
+//! Rendering item bodies from Markdown
+//!
+//! Parsing is left to `pulldown_cmark`. Raw html is shown as text, links
+//! only keep urls [`is_safe_url`] allows and images are reduced to their
+//! alt text, so the output is safe to embed as is.
+
+use maud::PreEscaped;
+use pulldown_cmark::{html, CowStr, Event, LinkType, Parser, Tag, TagEnd};
+
+/// Render `text` to sanitized html
+pub fn render(text: &str) -> PreEscaped<String> {
+    let mut out = String::new();
+    html::push_html(&mut out, sanitize(Parser::new(text)));
+    PreEscaped(out)
+}
+
+/// What an opened emphasis, link or image got rendered as, to end it the
+/// same way
+enum Opened {
+    /// Passed through
+    Tag,
+    /// Rendered as html, closed with this
+    Html(&'static str),
+    /// Its markup rendered as text, closed with this
+    Text(&'static str),
+    /// Dropped, content and all being kept
+    Nothing,
+}
+
+fn sanitize<'a>(events: impl Iterator<Item = Event<'a>>) -> impl Iterator<Item = Event<'a>> {
+    let mut opened = vec![];
+    // emphasis only opens at the start of a word, so `2*3*4` stays as is
+    let mut after_word = false;
+    events.filter_map(move |event| {
+        let event = match event {
+            Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+            Event::Start(Tag::HtmlBlock) => Event::Start(Tag::Paragraph),
+            Event::End(TagEnd::HtmlBlock) => Event::End(TagEnd::Paragraph),
+            Event::Start(tag @ (Tag::Emphasis | Tag::Strong)) if after_word => {
+                let marker = match tag {
+                    Tag::Emphasis => "*",
+                    _ => "**",
+                };
+                opened.push(Opened::Text(marker));
+                Event::Text(marker.into())
+            }
+            Event::Start(Tag::Emphasis | Tag::Strong) => {
+                opened.push(Opened::Tag);
+                event
+            }
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                ..
+            }) => {
+                let url = match link_type {
+                    LinkType::Email => CowStr::from(format!("mailto:{dest_url}")),
+                    _ => dest_url,
+                };
+                if !is_safe_url(&url) {
+                    opened.push(Opened::Nothing);
+                    return None;
+                }
+                opened.push(Opened::Html("</a>"));
+                let mut html = String::from("<a href=\"");
+                escape_into(&mut html, &url);
+                html.push_str("\" rel=\"noopener noreferrer\">");
+                Event::InlineHtml(html.into())
+            }
+            Event::Start(Tag::Image { .. }) => {
+                opened.push(Opened::Nothing);
+                return None;
+            }
+            Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Link | TagEnd::Image) => {
+                match opened.pop().expect("events are balanced") {
+                    Opened::Tag => event,
+                    Opened::Html(html) => Event::InlineHtml(html.into()),
+                    Opened::Text(marker) => Event::Text(marker.into()),
+                    Opened::Nothing => return None,
+                }
+            }
+            event => event,
+        };
+        after_word = match &event {
+            Event::Text(text) | Event::Code(text) => {
+                text.chars().next_back().is_some_and(char::is_alphanumeric)
+            }
+            _ => false,
+        };
+        Some(event)
+    })
+}
+
+/// Relative urls, or absolute ones with a harmless scheme
+///
+/// Protocol relative urls, also when spelled with a `\`, point to other
+/// hosts and count as absolute.
+fn is_safe_url(url: &str) -> bool {
+    if url.is_empty() || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+    if url.starts_with("//") || url.starts_with('\\') || url.starts_with("/\\") {
+        return false;
+    }
+    match url.find([':', '/', '?', '#']) {
+        Some(i) if url[i..].starts_with(':') => {
+            let scheme = url[..i].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto")
+        }
+        _ => true,
+    }
+}
+
+fn escape_into(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_blocks_and_inlines() {
+        let html = render(
+            "# Todo\n\n- **milk**\n- `eggs`\n\n1. [shop](https://example.com)\n\n```\n<b>\n```",
+        );
+        assert_eq!(
+            html.0,
+            "<h1>Todo</h1>\n<ul>\n<li><strong>milk</strong></li>\n<li><code>eggs</code></li>\n</ul>\n\
+             <ol>\n<li><a href=\"https://example.com\" rel=\"noopener noreferrer\">shop</a></li>\n</ol>\n\
+             <pre><code>&lt;b&gt;\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn escapes_html_and_drops_unsafe_links() {
+        let html = render("a <script>alert(1)</script> < b [x](javascript:void) [y](JavaScript:x)");
+        assert_eq!(
+            html.0,
+            "<p>a &lt;script&gt;alert(1)&lt;/script&gt; &lt; b x y</p>\n"
+        );
+        assert_eq!(
+            render("<img src=x onerror=alert(1)>").0,
+            "<p>&lt;img src=x onerror=alert(1)&gt;</p>\n"
+        );
+        assert_eq!(render("![alt](https://x/y.png)").0, "<p>alt</p>\n");
+        assert_eq!(render("[x](//evil.com/x)").0, "<p>x</p>\n");
+        assert!(is_safe_url("/list/1"));
+        assert!(is_safe_url("mailto:a@example.com"));
+        assert!(!is_safe_url("java\tscript:x"));
+        assert!(!is_safe_url("data:text/html,x"));
+        assert!(!is_safe_url("//evil.com/x"));
+        assert!(!is_safe_url("\\\\evil.com/x"));
+        assert!(!is_safe_url("/\\evil.com/x"));
+    }
+
+    #[test]
+    fn keeps_text_that_only_looks_like_html() {
+        assert_eq!(render("a<b and c>d").0, "<p>a&lt;b and c&gt;d</p>\n");
+        assert_eq!(
+            render("<https://x>").0,
+            "<p><a href=\"https://x\" rel=\"noopener noreferrer\">https://x</a></p>\n"
+        );
+        assert_eq!(render("1 <2> 3").0, "<p>1 &lt;2&gt; 3</p>\n");
+    }
+
+    #[test]
+    fn only_emphasizes_whole_words() {
+        assert_eq!(render("2*3*4").0, "<p>2*3*4</p>\n");
+        assert_eq!(render("a**b**c").0, "<p>a**b**c</p>\n");
+        assert_eq!(render("*a* b").0, "<p><em>a</em> b</p>\n");
+        assert_eq!(render("(**a**)").0, "<p>(<strong>a</strong>)</p>\n");
+    }
+}
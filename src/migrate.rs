@@ -0,0 +1,252 @@
+//! Upgrading databases written by earlier versions
+//!
+//! [`DB_VERSION_TABLE`] records the version of a database. The ones from
+//! before it existed hold a single list of items, which gets moved into
+//! [`DEFAULT_LIST_ID`].
+
+use std::marker::PhantomData;
+
+use anyhow::{bail, Context};
+use redb::{
+    Key, ReadTransaction, ReadableTable, TableDefinition, TableError, TableHandle, TypeName, Value,
+    WriteTransaction,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{ItemData, ItemId, ItemValue, DB_VERSION_TABLE, ITEM_ORDER_TABLE, ITEM_TABLE};
+use crate::service::{index_item, DEFAULT_LIST_ID};
+use crate::sortid::SortId;
+
+/// Bumped, with a new upgrade step in [`run`], whenever a stored value
+/// changes its layout
+pub const DB_VERSION: u64 = 1;
+
+/// Order of all items, from before lists
+const ITEM_ORDER_TABLE_V0: TableDefinition<SortId, ItemIdV0> = TableDefinition::new("item_order");
+
+/// Upgrade the database to [`DB_VERSION`]
+pub fn run(dbtx: &WriteTransaction) -> anyhow::Result<()> {
+    let version = dbtx
+        .open_table(DB_VERSION_TABLE)?
+        .get(())?
+        .map(|v| v.value());
+    match version {
+        Some(DB_VERSION) => return Ok(()),
+        Some(version) => bail!("Unsupported database version {version}"),
+        None if has_table(dbtx, ITEM_TABLE.name())? => upgrade_single_list(dbtx)?,
+        // a new database
+        None => {}
+    }
+    dbtx.open_table(DB_VERSION_TABLE)?.insert((), DB_VERSION)?;
+    Ok(())
+}
+
+/// Fail unless the database is at [`DB_VERSION`], for using it without
+/// upgrading it
+pub fn check_version(dbtx: &ReadTransaction) -> anyhow::Result<()> {
+    let version = match dbtx.open_table(DB_VERSION_TABLE) {
+        Ok(table) => table.get(())?.map(|v| v.value()),
+        Err(TableError::TableDoesNotExist(_)) => None,
+        Err(e) => return Err(e.into()),
+    };
+    if version != Some(DB_VERSION) {
+        bail!("Database is not at version {DB_VERSION}, start the server once to upgrade it");
+    }
+    Ok(())
+}
+
+fn has_table(dbtx: &WriteTransaction, name: &str) -> anyhow::Result<bool> {
+    Ok(dbtx.list_tables()?.any(|table| table.name() == name))
+}
+
+/// Upgrade a database from before lists, moving all items into
+/// [`DEFAULT_LIST_ID`]
+///
+/// Item ids were ordered by their bytes back then, so the item table gets
+/// recreated. The order table is rebuilt from the items' `SortId`s.
+fn upgrade_single_list(dbtx: &WriteTransaction) -> anyhow::Result<()> {
+    let items = dbtx
+        .open_table(TableDefinition::<ItemIdV0, Raw<ItemValue>>::new(
+            ITEM_TABLE.name(),
+        ))?
+        .iter()?
+        .map(|entry| entry.map(|(k, v)| (k.value(), v.value().to_vec())))
+        .collect::<Result<Vec<_>, _>>()?;
+    dbtx.delete_table(ITEM_ORDER_TABLE_V0)?;
+    dbtx.delete_table(ITEM_TABLE)?;
+
+    let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+    let mut item_order_table = dbtx.open_multimap_table(ITEM_ORDER_TABLE)?;
+    for (item_id, bytes) in items {
+        let item = ItemValue::from(
+            bincode::deserialize::<ItemValueV0>(&bytes)
+                .with_context(|| format!("Failed to read item {}", item_id.0))?,
+        );
+        item_order_table.insert(DEFAULT_LIST_ID, (item.sort_id.clone(), item_id))?;
+        index_item(dbtx, item_id, None, Some(&item.data))?;
+        item_table.insert(item_id, item)?;
+    }
+    Ok(())
+}
+
+/// Bytes of a `V`, as stored
+#[derive(Debug)]
+struct Raw<V>(PhantomData<V>);
+
+impl<V: Value + 'static> Value for Raw<V> {
+    type SelfType<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        V::fixed_width()
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        data
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        value
+    }
+
+    fn type_name() -> TypeName {
+        V::type_name()
+    }
+}
+
+/// `ItemId` from before lists, ordered by its little endian bytes
+#[derive(Debug)]
+struct ItemIdV0;
+
+impl Value for ItemIdV0 {
+    type SelfType<'a> = ItemId;
+
+    type AsBytes<'a> = [u8; 8];
+
+    fn fixed_width() -> Option<usize> {
+        ItemId::fixed_width()
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        ItemId::from_bytes(data)
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        ItemId::as_bytes(value)
+    }
+
+    fn type_name() -> TypeName {
+        ItemId::type_name()
+    }
+}
+
+impl Key for ItemIdV0 {
+    fn compare(data1: &[u8], data2: &[u8]) -> std::cmp::Ordering {
+        data1.cmp(data2)
+    }
+}
+
+/// `ItemData` from before lists
+#[derive(Debug, Serialize, Deserialize)]
+struct ItemDataV0 {
+    title: String,
+    body: String,
+}
+
+/// `ItemValue` from before lists
+#[derive(Debug, Serialize, Deserialize)]
+struct ItemValueV0 {
+    sort_id: SortId,
+    data: ItemDataV0,
+}
+
+impl From<ItemValueV0> for ItemValue {
+    fn from(value: ItemValueV0) -> Self {
+        Self {
+            list_id: DEFAULT_LIST_ID,
+            sort_id: value.sort_id,
+            data: ItemData {
+                title: value.data.title,
+                body: value.data.body,
+                tags: vec![],
+                completed_at: None,
+                due: None,
+            },
+            revision: 0,
+            parent: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::service::tests::with_temp_db;
+    use crate::service::Service;
+
+    #[test]
+    fn upgrades_single_list_database() -> anyhow::Result<()> {
+        with_temp_db(|opts| {
+            let item = |sort_id: u8, title: &str| ItemValueV0 {
+                sort_id: SortId::from(vec![sort_id]),
+                data: ItemDataV0 {
+                    title: title.into(),
+                    body: String::new(),
+                },
+            };
+            Database::open(&opts.db)?.write_with(|dbtx| {
+                let mut item_table = dbtx.open_table(
+                    TableDefinition::<ItemIdV0, Raw<ItemValue>>::new(ITEM_TABLE.name()),
+                )?;
+                let mut item_order_table = dbtx.open_table(ITEM_ORDER_TABLE_V0)?;
+                // ids 1 and 256 are in the opposite order as bytes
+                for (id, value) in [(1, item(0x80, "first")), (256, item(0x40, "zeroth"))] {
+                    item_order_table.insert(value.sort_id.clone(), ItemId(id))?;
+                    item_table.insert(ItemId(id), bincode::serialize(&value)?.as_slice())?;
+                }
+                Ok(())
+            })?;
+
+            let service = Service::new(opts.clone())?;
+            let user_id = service.create_user("user", "password", true)?;
+            drop(service);
+            // the upgraded database opens like any other
+            let service = Service::new(opts.clone())?;
+            let titles: Vec<_> = service
+                .read_items(user_id, DEFAULT_LIST_ID)?
+                .into_iter()
+                .map(|item| (item.id, item.data.title))
+                .collect();
+            assert_eq!(
+                titles,
+                [(ItemId(256), "zeroth".into()), (ItemId(1), "first".into())]
+            );
+            // items from before the search index got indexed
+            assert_eq!(service.search_items(user_id, "zeroth")?.len(), 1);
+            assert!(service.fsck(false)?.is_empty());
+            Ok(())
+        })
+    }
+}
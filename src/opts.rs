@@ -1,16 +1,64 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Opts {
-    #[arg(long, short, default_value = "[::1]:3000")]
+    #[arg(long, short, default_value = "[::1]:3000", global = true)]
     pub listen: String,
 
-    #[arg(long, default_value = "db.redb")]
+    #[arg(long, default_value = "db.redb", global = true)]
     pub db: PathBuf,
 
-    #[arg(long, env = "DEBUG_DELAY")]
+    /// Rebalance a list when a newly generated `SortId` gets longer than
+    /// this many bytes
+    #[arg(long, default_value = "32", global = true)]
+    pub sort_id_rebalance_len: usize,
+
+    /// Don't mark cookies as `Secure`, needed when serving over plain http
+    /// on anything other than localhost
+    #[arg(long, env = "INSECURE_COOKIES", global = true)]
+    pub insecure_cookies: bool,
+
+    #[arg(long, env = "DEBUG_DELAY", global = true)]
     pub debug_delay: bool,
+
+    /// What to do, `serve` if not given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Run the http server
+    Serve,
+    /// Create a user account, reading its password from stdin
+    CreateUser {
+        username: String,
+
+        /// Make the user an admin
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Write all users, lists and items as JSON
+    Export {
+        /// File to write to, stdout if not given
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Replace all users, lists and items with the ones in a JSON export
+    Import { input: PathBuf },
+    /// Check the consistency of items and their order, without changing
+    /// the database file
+    Check {
+        /// Also check the integrity of the database file, fix inconsistent
+        /// items and rebuild their order from them
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Rebalance the `SortId`s of all lists and sub-items, shortening them
+    Rebalance,
+    /// Give unused space in the database file back to the filesystem
+    Compact,
 }
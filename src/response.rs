@@ -5,10 +5,22 @@ pub trait ResponseBuilderExt {
     fn cache_static(self) -> Self;
     fn cache_nostore(self) -> Self;
     fn status_not_found(self) -> Self;
+    /// Mark the response as depending on the headers used by
+    /// [`crate::service::RequestExt::response_kind`]
+    fn vary_response_kind(self) -> Self;
 
     fn body_html(self, html: maud::PreEscaped<String>) -> Self::Response;
+    fn body_json(self, value: &impl serde::Serialize) -> Self::Response;
     fn body_static_str(self, content_type: &str, content: &'static str) -> Self::Response;
     fn body_static_bytes(self, content_type: &str, content: &'static [u8]) -> Self::Response;
+    fn body_event_stream(self, stream: crate::sse::EventStream) -> Self::Response;
+    /// A file the browser should download as `filename`
+    fn body_attachment(
+        self,
+        content_type: &str,
+        filename: &str,
+        content: Vec<u8>,
+    ) -> Self::Response;
 }
 
 impl ResponseBuilderExt for astra::ResponseBuilder {
@@ -27,12 +39,24 @@ impl ResponseBuilderExt for astra::ResponseBuilder {
         self.status(hyper::StatusCode::NOT_FOUND)
     }
 
+    fn vary_response_kind(self) -> Self {
+        self.header(header::VARY, "Accept, HX-Request")
+    }
+
     fn body_html(self, html: maud::PreEscaped<String>) -> Self::Response {
         self.header(header::CONTENT_TYPE, "text/html")
             .body(astra::Body::new(html.into_string()))
             .unwrap()
     }
 
+    fn body_json(self, value: &impl serde::Serialize) -> Self::Response {
+        self.header(header::CONTENT_TYPE, "application/json")
+            .body(astra::Body::new(
+                serde_json::to_vec(value).expect("json serialization can't fail"),
+            ))
+            .unwrap()
+    }
+
     fn body_static_str(self, content_type: &str, content: &'static str) -> Self::Response {
         self.header(header::CONTENT_TYPE, content_type)
             .body(astra::Body::new(content))
@@ -43,4 +67,26 @@ impl ResponseBuilderExt for astra::ResponseBuilder {
             .body(astra::Body::new(content))
             .unwrap()
     }
+
+    fn body_event_stream(self, stream: crate::sse::EventStream) -> Self::Response {
+        self.header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(astra::Body::wrap_reader(stream))
+            .unwrap()
+    }
+
+    fn body_attachment(
+        self,
+        content_type: &str,
+        filename: &str,
+        content: Vec<u8>,
+    ) -> Self::Response {
+        self.header(header::CONTENT_TYPE, content_type)
+            .header(
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            )
+            .body(astra::Body::new(content))
+            .unwrap()
+    }
 }
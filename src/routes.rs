@@ -1,20 +1,114 @@
 use std::str::FromStr;
 
+use anyhow::Context;
 use astra::ResponseBuilder;
-use hyper::{Response, StatusCode};
+use hyper::{header, Response, StatusCode};
 use maud::html;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-use crate::db::{Item, ItemData, ItemId};
+use crate::api::ApiItem;
+use crate::db::{Access, Item, ItemData, ItemId, ListId, ListValue};
 use crate::fragment;
 use crate::response::ResponseBuilderExt;
-use crate::service::Service;
+use crate::service::{BadRequestError, ConflictError, RequestExt, ResponseKind, Service, Session};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ItemOrder {
+    pub prev: Option<ItemId>,
+    pub curr: ItemId,
+    pub next: Option<ItemId>,
+    /// Target list, if the item was dropped into a different list
+    pub list: Option<ListId>,
+    /// Target parent item, if dropped into its sub-items
+    pub parent: Option<ItemId>,
+}
+
+/// An item as submitted by the item forms
+///
+/// Whether it's done is changed separately, see [`CompleteForm`].
+#[derive(Debug, Deserialize)]
+pub struct ItemForm {
+    title: String,
+    #[serde(default)]
+    body: String,
+    /// Comma separated
+    #[serde(default)]
+    tags: String,
+    /// `YYYY-MM-DD`, or empty
+    #[serde(default)]
+    due: String,
+    /// Revision of the item the edit was based on
+    revision: Option<u64>,
+}
+
+impl From<ItemForm> for ItemData {
+    fn from(form: ItemForm) -> Self {
+        Self {
+            title: form.title,
+            body: form.body,
+            tags: ItemData::parse_tags(&form.tags),
+            completed_at: None,
+            due: Some(form.due).filter(|due| !due.is_empty()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompleteForm {
+    completed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportForm {
+    /// The uploaded export, as read from the file by the browser
+    data: String,
+}
+
+/// Body of an item being edited, to show rendered or back in the editor
+#[derive(Debug, Deserialize)]
+pub struct BodyPreview {
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    preview: bool,
+}
+
+/// Tag a list view is filtered by, sent along with the requests made from it
+#[derive(Debug, Default, Deserialize)]
+pub struct TagFilter {
+    tag: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareForm {
+    username: String,
+    /// `read`, `edit` or `none`
+    access: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ItemBatchOrder {
     prev: Option<ItemId>,
-    curr: ItemId,
+    /// Comma separated ids of the items to move, in order
+    items: String,
     next: Option<ItemId>,
+    /// Target list, if the items were dropped into a different list
+    list: Option<ListId>,
+    /// Target parent item, if dropped into its sub-items
+    parent: Option<ItemId>,
 }
 
 impl Service {
@@ -22,70 +116,519 @@ impl Service {
         &self,
         _req: &mut astra::Request,
         _: &matchit::Params,
+        session: &Session,
     ) -> anyhow::Result<astra::Response> {
-        Ok(ResponseBuilder::new().body_html(self.home_page(None)?))
+        let user_id = session.user_id()?;
+        // the inbox is created on login, but shares can be taken away since
+        let Some((list_id, _, _)) = self.read_lists(user_id)?.into_iter().next() else {
+            return Ok(ResponseBuilder::new().body_html(self.no_lists_page(session)?));
+        };
+        Ok(ResponseBuilder::new().body_html(self.list_page(session, list_id, None, None)?))
     }
 
-    pub fn item_get(
+    pub fn login_get(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        if session.value.user_id.is_some() {
+            return Ok(see_other(req, "/"));
+        }
+        let mut resp =
+            ResponseBuilder::new().body_html(fragment::login_page(session, self.has_users()?));
+        // the login form needs a CSRF token that's still valid when it's sent
+        if !session.is_stored {
+            resp.headers_mut()
+                .insert(header::SET_COOKIE, self.store_session(session)?);
+        }
+        Ok(resp)
+    }
+
+    pub fn login_post(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let form: LoginForm = parse_form_body(req)?;
+        let Some(user_id) = self.authenticate(&form.username, &form.password)? else {
+            // htmx doesn't swap in error responses
+            return Ok(ResponseBuilder::new().body_html(fragment::login_form(
+                Some("Invalid username or password"),
+                self.has_users()?,
+            )));
+        };
+        self.create_inbox_if_missing(user_id)?;
+        let new_session = self.login_session(session.id, user_id)?;
+        let mut resp = see_other(req, "/");
+        resp.headers_mut()
+            .insert(header::SET_COOKIE, self.session_cookie(&new_session));
+        Ok(resp)
+    }
+
+    pub fn logout(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        self.delete_session(session.id)?;
+        let mut resp = see_other(req, "/login");
+        resp.headers_mut()
+            .insert(header::SET_COOKIE, self.session_cookie_removal());
+        Ok(resp)
+    }
+
+    pub fn list_get(
+        &self,
+        req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let list_id: ListId = param(params, "list_id")?;
+        let TagFilter { tag } = parse_form(req.uri().query().unwrap_or_default())?;
+
+        Ok(ResponseBuilder::new().body_html(self.list_page(
+            session,
+            list_id,
+            None,
+            tag.as_deref(),
+        )?))
+    }
+
+    /// Items of a list, for reloading them after a change
+    pub fn list_items(
+        &self,
+        req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let list_id: ListId = param(params, "list_id")?;
+        let TagFilter { tag } = parse_form(req.uri().query().unwrap_or_default())?;
+
+        Ok(ResponseBuilder::new().body_html(self.list_items_form(
+            session.user_id()?,
+            list_id,
+            tag.as_deref(),
+        )?))
+    }
+
+    /// Event stream of changes to the items of a list
+    pub fn list_events(
         &self,
         _req: &mut astra::Request,
         params: &matchit::Params,
+        session: &Session,
     ) -> anyhow::Result<astra::Response> {
-        let item_id = ItemId::from_str(params.get("id").expect("id param not in the path params"))?;
+        let list_id: ListId = param(params, "list_id")?;
+        let stream = self.subscribe_list(session, list_id)?;
 
-        let item_data = self.load_item(item_id)?;
+        Ok(ResponseBuilder::new().body_event_stream(stream))
+    }
 
-        Ok(ResponseBuilder::new().body_html(self.home_page(Some((item_id, item_data)))?))
+    pub fn list_share_get(
+        &self,
+        _req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let list_id: ListId = param(params, "list_id")?;
+        Ok(ResponseBuilder::new().body_html(self.list_share_page(session, list_id)?))
     }
+
+    pub fn list_share_post(
+        &self,
+        req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let list_id: ListId = param(params, "list_id")?;
+        let form: ShareForm = parse_form_body(req)?;
+        let access = match form.access.as_str() {
+            "read" => Some(Access::Read),
+            "edit" => Some(Access::Edit),
+            "none" => None,
+            _ => return Err(BadRequestError("invalid access").into()),
+        };
+        self.share_list(session.user_id()?, list_id, &form.username, access)?;
+        Ok(ResponseBuilder::new().body_html(fragment::list_shares(
+            list_id,
+            &self.read_list_shares(session.user_id()?, list_id)?,
+        )))
+    }
+
+    pub fn list_create(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let list_value: ListValue = parse_form_body(req)?;
+        let list_id = self.create_list(session.user_id()?, list_value)?;
+        Ok(ResponseBuilder::new()
+            .header("HX-Redirect", format!("/list/{list_id}"))
+            .body_static_bytes("text/html", &[]))
+    }
+
+    pub fn item_get(
+        &self,
+        req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let item_id: ItemId = param(params, "id")?;
+
+        let item = self.load_item(session.user_id()?, item_id)?;
+
+        if params.get("list_id").is_some() && param::<ListId>(params, "list_id")? != item.list_id {
+            return Ok(not_found_404());
+        }
+
+        let resp = ResponseBuilder::new().vary_response_kind();
+        Ok(match RequestExt(req).response_kind() {
+            ResponseKind::Json => resp.body_json(&ApiItem::from_value(item_id, item)),
+            ResponseKind::Fragment => {
+                let (_, access) = self.load_list(session.user_id()?, item.list_id)?;
+                resp.body_html(fragment::item_edit_form(
+                    Some((item_id, item)),
+                    Access::Edit <= access,
+                    None,
+                ))
+            }
+            ResponseKind::Page => resp.body_html(self.list_page(
+                session,
+                item.list_id,
+                Some((item_id, item)),
+                None,
+            )?),
+        })
+    }
+
     pub fn item_order(
         &self,
         req: &mut astra::Request,
         _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let item_order: ItemOrder = parse_form_body(req)?;
+        self.change_item_order(
+            session.user_id()?,
+            item_order.prev,
+            item_order.curr,
+            item_order.next,
+            item_order.list,
+            item_order.parent,
+        )?;
+        Ok(ResponseBuilder::new().body_html(fragment::undo_toast(Some("Item moved"), true)))
+    }
+
+    pub fn item_order_batch(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let user_id = session.user_id()?;
+        let body = std::io::read_to_string(req.body_mut().reader())?;
+        let batch_order: ItemBatchOrder = parse_form(&body)?;
+        let TagFilter { tag } = parse_form(&body)?;
+        let item_ids = batch_order
+            .items
+            .split(',')
+            .filter(|id| !id.is_empty())
+            .map(ItemId::from_str)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context(BadRequestError("invalid item id"))?;
+        let Some(first_id) = item_ids.first() else {
+            return Err(BadRequestError("no items to move").into());
+        };
+        // the list the items were dragged from gets re-rendered
+        let source_list_id = self.load_item(user_id, *first_id)?.list_id;
+        self.change_items_order(
+            user_id,
+            batch_order.prev,
+            &item_ids,
+            batch_order.next,
+            batch_order.list,
+            batch_order.parent,
+        )?;
+        Ok(ResponseBuilder::new().body_html(html! {
+            (self.list_items_form(user_id, source_list_id, tag.as_deref())?)
+            (fragment::undo_toast(Some("Items moved"), true))
+        }))
+    }
+
+    pub fn undo_last(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
     ) -> anyhow::Result<astra::Response> {
-        let item_order: ItemOrder = serde_urlencoded::from_reader(req.body_mut().reader())?;
-        self.change_item_order(item_order.prev, item_order.curr, item_order.next)?;
-        Ok(ResponseBuilder::new().body_static_bytes("foo", &[]))
+        let TagFilter { tag } = parse_form_body(req)?;
+        let user_id = session.user_id()?;
+        let (list_id, restored) = self.undo(user_id)?;
+        Ok(ResponseBuilder::new()
+            .header("HX-Push-Url", fragment::list_url(list_id, tag.as_deref()))
+            .body_html(html! {
+                (self.list_items_form(user_id, list_id, tag.as_deref())?)
+                @if let Some(item) = restored {
+                    (fragment::item_edit_form(Some(item), true, Some("item-edit")))
+                }
+                (fragment::undo_toast(None, true))
+            }))
     }
 
     pub fn item_update(
         &self,
         req: &mut astra::Request,
         params: &matchit::Params,
+        session: &Session,
     ) -> anyhow::Result<astra::Response> {
-        let item_id = ItemId::from_str(params.get("id").expect("id param not in the path params"))?;
-        let item_data: ItemData = serde_urlencoded::from_reader(req.body_mut().reader())?;
-        self.update_item(item_id, &item_data)?;
-        Ok(ResponseBuilder::new().body_html(self.home_page(Some((item_id, item_data)))?))
+        let item_id: ItemId = param(params, "id")?;
+        let body = std::io::read_to_string(req.body_mut().reader())?;
+        let form: ItemForm = parse_form(&body)?;
+        let TagFilter { tag } = parse_form(&body)?;
+        let revision = form.revision;
+        let item_data = ItemData::from(form);
+        let user_id = session.user_id()?;
+        let kind = RequestExt(req).response_kind();
+        match self.update_item(user_id, item_id, &item_data, revision) {
+            Err(error) if error.is::<ConflictError>() && kind == ResponseKind::Fragment => {
+                let current = self.load_item(user_id, item_id)?;
+                return Ok(ResponseBuilder::new()
+                    .status(StatusCode::CONFLICT)
+                    // the form targets the list, but it's the form that gets replaced
+                    .header("HX-Retarget", "#item-edit")
+                    .body_html(fragment::item_conflict_form(item_id, &current, &item_data)));
+            }
+            res => res?,
+        }
+        let item = self.load_item(user_id, item_id)?;
+        let resp = ResponseBuilder::new().vary_response_kind();
+        Ok(match kind {
+            ResponseKind::Json => resp.body_json(&ApiItem::from_value(item_id, item)),
+            ResponseKind::Fragment => resp.body_html(html! {
+                (self.list_items_form(user_id, item.list_id, tag.as_deref())?)
+                (fragment::undo_toast(Some("Item saved"), true))
+            }),
+            ResponseKind::Page => resp.body_html(self.list_page(
+                session,
+                item.list_id,
+                Some((item_id, item)),
+                None,
+            )?),
+        })
+    }
+
+    pub fn item_complete(
+        &self,
+        req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let item_id: ItemId = param(params, "id")?;
+        let CompleteForm { completed } = parse_form_body(req)?;
+        let user_id = session.user_id()?;
+        self.complete_item(user_id, item_id, completed)?;
+        let item = self.load_item(user_id, item_id)?;
+        Ok(ResponseBuilder::new().body_html(Item::from_value(item_id, item).item_line(true)))
     }
 
     pub fn item_create(
         &self,
         req: &mut astra::Request,
-        _: &matchit::Params,
+        params: &matchit::Params,
+        session: &Session,
     ) -> anyhow::Result<astra::Response> {
-        let item_data: ItemData = serde_urlencoded::from_reader(req.body_mut().reader())?;
-        let item_id = self.create_item(item_data.clone())?;
+        let list_id: ListId = param(params, "list_id")?;
+        let body = std::io::read_to_string(req.body_mut().reader())?;
+        let form: ItemForm = parse_form(&body)?;
+        let TagFilter { tag } = parse_form(&body)?;
+        let mut item_data = ItemData::from(form);
+        // new items show up in the filtered view they were added in
+        if let Some(tag) = &tag {
+            if !item_data.tags.contains(tag) {
+                item_data.tags.push(tag.clone());
+            }
+        }
+        let user_id = session.user_id()?;
+        let item_id = self.create_item(user_id, list_id, item_data)?;
+        let item = self.load_item(user_id, item_id)?;
         Ok(ResponseBuilder::new().body_html(html! {
-            (Item::items_form("items", &self.read_items()?))
-            (fragment::item_edit_form(Some((item_id, item_data)), Some("item-edit")))
+            (self.list_items_form(user_id, list_id, tag.as_deref())?)
+            (fragment::item_edit_form(Some((item_id, item)), true, Some("item-edit")))
         }))
     }
 
+    pub fn item_delete(
+        &self,
+        req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let item_id: ItemId = param(params, "id")?;
+        let TagFilter { tag } = parse_form_body(req)?;
+        let user_id = session.user_id()?;
+        let list_id = self.delete_item(user_id, item_id)?;
+        Ok(ResponseBuilder::new()
+            .header("HX-Push-Url", fragment::list_url(list_id, tag.as_deref()))
+            .body_html(html! {
+                (self.list_items_form(user_id, list_id, tag.as_deref())?)
+                (fragment::item_edit_form(None, true, Some("item-edit")))
+            }))
+    }
+
+    pub fn search(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let SearchQuery { q } = parse_form(req.uri().query().unwrap_or_default())?;
+        let items = self.search_items(session.user_id()?, &q)?;
+
+        let resp = ResponseBuilder::new().vary_response_kind();
+        Ok(match RequestExt(req).response_kind() {
+            ResponseKind::Json => {
+                resp.body_json(&items.into_iter().map(ApiItem::from).collect::<Vec<_>>())
+            }
+            ResponseKind::Fragment => resp.body_html(fragment::search_results(
+                &q,
+                &items,
+                &self.read_editable_list_ids(session.user_id()?)?,
+            )),
+            ResponseKind::Page => resp.body_html(self.search_page(session, &q, &items)?),
+        })
+    }
+
+    pub fn upcoming(
+        &self,
+        _req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        Ok(ResponseBuilder::new().body_html(self.upcoming_page(session)?))
+    }
+
+    pub fn trash(
+        &self,
+        _req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        Ok(ResponseBuilder::new().body_html(self.trash_page(session)?))
+    }
+
+    pub fn backup(
+        &self,
+        _req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        self.check_admin(session.user_id()?)?;
+        Ok(ResponseBuilder::new().body_html(self.backup_page(session)?))
+    }
+
+    pub fn backup_export(
+        &self,
+        _req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        self.check_admin(session.user_id()?)?;
+        let export = serde_json::to_vec_pretty(&self.export_db()?)?;
+        Ok(ResponseBuilder::new().cache_nostore().body_attachment(
+            "application/json",
+            "htmx-sorta-export.json",
+            export,
+        ))
+    }
+
+    pub fn backup_import(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        self.check_admin(session.user_id()?)?;
+        let ImportForm { data } = parse_form_body(req)?;
+        let export = serde_json::from_str(&data).context(BadRequestError("invalid export"))?;
+        self.import_db(&export)?;
+        // all sessions are gone, including this one
+        let mut resp = see_other(req, "/login");
+        resp.headers_mut()
+            .insert(header::SET_COOKIE, self.session_cookie_removal());
+        Ok(resp)
+    }
+
+    pub fn trash_restore(
+        &self,
+        _req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let item_id: ItemId = param(params, "id")?;
+        let user_id = session.user_id()?;
+        self.restore_item(user_id, item_id)?;
+        Ok(ResponseBuilder::new().body_html(fragment::trash_list(&self.read_trash(user_id)?)))
+    }
+
+    pub fn trash_purge(
+        &self,
+        _req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let item_id: ItemId = param(params, "id")?;
+        let user_id = session.user_id()?;
+        self.purge_item(user_id, item_id)?;
+        Ok(ResponseBuilder::new().body_html(fragment::trash_list(&self.read_trash(user_id)?)))
+    }
+
     pub fn item_edit(
         &self,
         _req: &mut astra::Request,
         params: &matchit::Params,
+        session: &Session,
     ) -> anyhow::Result<astra::Response> {
-        let item_id = ItemId::from_str(params.get("id").expect("id param not in the path params"))?;
-        let item_data = self.load_item(item_id)?;
-        Ok(ResponseBuilder::new()
-            .body_html(fragment::item_edit_form(Some((item_id, item_data)), None)))
+        let item_id: ItemId = param(params, "id")?;
+        let user_id = session.user_id()?;
+        let item = self.load_item(user_id, item_id)?;
+        let (_, access) = self.load_list(user_id, item.list_id)?;
+        Ok(ResponseBuilder::new().body_html(fragment::item_edit_form(
+            Some((item_id, item)),
+            Access::Edit <= access,
+            None,
+        )))
+    }
+
+    pub fn item_preview(
+        &self,
+        req: &mut astra::Request,
+        _: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        session.user_id()?;
+        let BodyPreview { body, preview } = parse_form_body(req)?;
+        Ok(ResponseBuilder::new().body_html(fragment::item_body_field(&body, true, preview)))
+    }
+
+    pub fn item_history(
+        &self,
+        _req: &mut astra::Request,
+        params: &matchit::Params,
+        session: &Session,
+    ) -> anyhow::Result<astra::Response> {
+        let item_id: ItemId = param(params, "id")?;
+        Ok(ResponseBuilder::new().body_html(self.item_history_page(session, item_id)?))
     }
 
     pub fn favicon_ico(
         &self,
         _req: &mut astra::Request,
         _: &matchit::Params,
+        _session: &Session,
     ) -> anyhow::Result<astra::Response> {
         Ok(Response::builder()
             .cache_static()
@@ -96,6 +639,7 @@ impl Service {
         &self,
         _req: &mut astra::Request,
         _: &matchit::Params,
+        _session: &Session,
     ) -> anyhow::Result<astra::Response> {
         Ok(Response::builder()
             // .cache_static()
@@ -112,6 +656,7 @@ impl Service {
         &self,
         _req: &mut astra::Request,
         _: &matchit::Params,
+        _session: &Session,
     ) -> anyhow::Result<astra::Response> {
         Ok(Response::builder()
             // .cache_static()
@@ -125,9 +670,37 @@ impl Service {
     }
 }
 
+/// Parse the path parameter `name`, rejecting malformed ones with
+/// [`BadRequestError`]
+pub fn param<T>(params: &matchit::Params, name: &str) -> anyhow::Result<T>
+where
+    T: FromStr<Err = anyhow::Error>,
+{
+    T::from_str(params.get(name).expect("param not in the path params"))
+        .context(BadRequestError("invalid path parameter"))
+}
+
+/// Deserialize a form body or query string, rejecting malformed ones with
+/// [`BadRequestError`]
+fn parse_form<T>(input: &str) -> anyhow::Result<T>
+where
+    T: DeserializeOwned,
+{
+    serde_urlencoded::from_str(input).context(BadRequestError("invalid form"))
+}
+
+fn parse_form_body<T>(req: &mut astra::Request) -> anyhow::Result<T>
+where
+    T: DeserializeOwned,
+{
+    parse_form(&std::io::read_to_string(req.body_mut().reader())?)
+}
+
 pub fn not_found_404() -> astra::Response {
     Response::builder().status(404).body_html(fragment::page(
         "PAGE NOT FOUND",
+        None,
+        None,
         html! {
             h2 { "This page does not exist. Sorry!" }
             p {
@@ -137,6 +710,50 @@ pub fn not_found_404() -> astra::Response {
     ))
 }
 
+/// Redirect to `location`, in a way htmx requests follow with a full page
+/// load
+pub fn see_other(req: &astra::Request, location: &str) -> astra::Response {
+    if RequestExt(req).response_kind() == ResponseKind::Fragment {
+        Response::builder()
+            .header("HX-Redirect", location)
+            .body_static_bytes("text/html", &[])
+    } else {
+        Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(header::LOCATION, location)
+            .body_static_bytes("text/html", &[])
+    }
+}
+
+pub fn forbidden_403() -> astra::Response {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body_html(fragment::page(
+            "FORBIDDEN",
+            None,
+            None,
+            html! {
+                h2 { "You don't have access to this page. Sorry!" }
+                p {
+                    a href="/" { "Return to the main page" }
+                }
+            },
+        ))
+}
+
+pub fn conflict_409() -> astra::Response {
+    Response::builder()
+        .status(StatusCode::CONFLICT)
+        .body_static_str("text/plain", "Conflict")
+}
+
+/// Plain text, as shown by the error toast of `script.js`
+pub fn bad_request_400(message: &'static str) -> astra::Response {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body_static_str("text/plain", message)
+}
+
 pub fn internal_error() -> astra::Response {
     Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -0,0 +1,67 @@
+//! Splitting item text into the terms of the search index
+
+use std::collections::BTreeMap;
+
+use crate::db::ItemData;
+
+/// How much more a term in the title or tags counts than one in the body
+const TITLE_WEIGHT: u32 = 3;
+
+/// Longer terms are cut, to keep index keys small
+const MAX_TERM_LEN: usize = 32;
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase().chars().take(MAX_TERM_LEN).collect())
+}
+
+/// Terms of `data` with their weight in it
+pub fn item_terms(data: &ItemData) -> BTreeMap<String, u32> {
+    let mut terms = BTreeMap::new();
+    for word in data.tags.iter().flat_map(|tag| words(tag)) {
+        *terms.entry(word).or_default() += TITLE_WEIGHT;
+    }
+    for word in words(&data.title) {
+        *terms.entry(word).or_default() += TITLE_WEIGHT;
+    }
+    for word in words(&data.body) {
+        *terms.entry(word).or_default() += 1;
+    }
+    terms
+}
+
+/// Distinct terms of a search query
+pub fn query_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<_> = words(query).collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn item_terms_weigh_title_higher() {
+        let terms = item_terms(&ItemData {
+            title: "Buy milk".into(),
+            body: "milk, not MILK-shake".into(),
+            tags: vec!["Shopping".into()],
+            completed_at: None,
+            due: None,
+        });
+        assert_eq!(terms.get("milk"), Some(&(TITLE_WEIGHT + 2)));
+        assert_eq!(terms.get("buy"), Some(&TITLE_WEIGHT));
+        assert_eq!(terms.get("shake"), Some(&1));
+        assert_eq!(terms.get("shopping"), Some(&TITLE_WEIGHT));
+        assert_eq!(terms.get(""), None);
+    }
+
+    #[test]
+    fn query_terms_dedup() {
+        assert_eq!(query_terms(" Milk milk, eggs "), vec!["eggs", "milk"]);
+        assert!(query_terms("  ,. ").is_empty());
+    }
+}
@@ -1,7 +1,9 @@
-use std::net;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::Ipv4Addr;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use std::{cmp, fmt, net};
 
 use anyhow::format_err;
 use db::{Item, ItemData, ItemId, ITEM_TABLE};
@@ -9,62 +11,525 @@ use hyper::http::HeaderValue;
 use hyper::{header, Method};
 use matchit::Match;
 use rate_limit::{conventional, pre};
-use redb::{ReadableTable, Table};
+use redb::{
+    MultimapTable, MultimapValue, ReadableMultimapTable, ReadableTable, ReadableTableMetadata,
+    Table, WriteTransaction,
+};
 use resiter::Map;
 use tracing::{debug, info, warn};
 
-use crate::db::{Database, ItemValue, ITEM_ORDER_TABLE};
+use crate::db::{
+    Access, Database, ItemEvent, ItemEventKind, ItemValue, ListId, ListValue, SessionId,
+    SessionValue, Siblings, TrashedItem, UndoEvent, UndoValue, UserId, UserValue,
+    ITEM_CHILD_ORDER_TABLE, ITEM_DUE_TABLE, ITEM_EVENT_TABLE, ITEM_ORDER_TABLE, ITEM_TAG_TABLE,
+    ITEM_TERM_TABLE, ITEM_TRASH_TABLE, LAST_ITEM_ID_TABLE, LIST_ACCESS_TABLE, LIST_TABLE,
+    LIST_USER_TABLE, SESSION_TABLE, UNDO_TABLE, USER_NAME_TABLE, USER_TABLE,
+};
+use crate::export::{Export, ExportItem, ExportList, ExportShare, ExportUser, EXPORT_VERSION};
 use crate::sortid::SortId;
-use crate::{db, opts, rate_limit, routes};
+use crate::{api, db, fsck, migrate, opts, rate_limit, routes, search, sse, util};
 
 type Router = matchit::Router<
     for<'a> fn(
         &Service,
         &'a mut astra::Request,
         &'a matchit::Params,
+        &'a Session,
     ) -> anyhow::Result<astra::Response>,
 >;
 
-type State = ();
+#[derive(Default)]
+struct State {
+    broadcaster: sse::Broadcaster,
+}
+
+/// A requested entity does not exist
+#[derive(Debug)]
+pub struct NotFoundError(pub &'static str);
+
+impl fmt::Display for NotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} not found", self.0)
+    }
+}
+
+impl std::error::Error for NotFoundError {}
+
+/// A request is invalid, regardless of the state of the database
+#[derive(Debug)]
+pub struct BadRequestError(pub &'static str);
+
+impl fmt::Display for BadRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for BadRequestError {}
+
+/// The request needs a logged in user
+#[derive(Debug)]
+pub struct UnauthorizedError;
+
+impl fmt::Display for UnauthorizedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("login required")
+    }
+}
+
+impl std::error::Error for UnauthorizedError {}
+
+/// The logged in user lacks access to a list
+#[derive(Debug)]
+pub struct ForbiddenError(pub &'static str);
+
+impl fmt::Display for ForbiddenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for ForbiddenError {}
+
+/// An edit was based on an outdated revision of an item
+#[derive(Debug)]
+pub struct ConflictError;
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("item was changed in the meantime")
+    }
+}
+
+impl std::error::Error for ConflictError {}
+
+/// Name of the cookie holding the [`SessionId`]
+pub const SESSION_COOKIE: &str = "session";
+/// How long a session is valid after its creation
+pub const SESSION_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Header carrying the session's CSRF token
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+/// Paths available without logging in
+const PUBLIC_PATHS: &[&str] = &[
+    "/login",
+    "/api/v1/login",
+    "/favicon.ico",
+    "/style.css",
+    "/script.js",
+];
+/// Public paths that don't look at the session at all
+const STATIC_PATHS: &[&str] = &["/favicon.ico", "/style.css", "/script.js"];
+/// Most items [`Service::search_items`] returns
+const SEARCH_RESULTS_MAX: usize = 50;
+
+/// A browser session, available to every route handler
+///
+/// New sessions are only stored, and get their cookie, once the login page
+/// needs their CSRF token, see [`Service::store_session`].
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: SessionId,
+    pub value: SessionValue,
+    /// Whether it's in the [`SESSION_TABLE`]
+    pub is_stored: bool,
+}
+
+impl Session {
+    fn new(user_id: Option<UserId>) -> Self {
+        let now = util::now_unix_secs();
+        Self {
+            id: SessionId::generate(),
+            value: SessionValue {
+                created_at: now,
+                expires_at: now + SESSION_TTL.as_secs(),
+                user_id,
+                csrf_token: util::random_bytes(),
+            },
+            is_stored: false,
+        }
+    }
+
+    /// The logged in user, which all non-public routes have
+    pub fn user_id(&self) -> anyhow::Result<UserId> {
+        Ok(self.value.user_id.ok_or(UnauthorizedError)?)
+    }
+
+    pub fn csrf_token(&self) -> String {
+        util::hex_encode(&self.value.csrf_token)
+    }
+
+    /// Check if the request carries this session's CSRF token
+    ///
+    /// JSON API requests don't need it: cross-site forms can't send json, and
+    /// cross-site `fetch` can't without CORS allowing it.
+    fn is_csrf_safe(&self, req: &astra::Request) -> bool {
+        let token = req
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| util::hex_decode(v).ok());
+        if let Some(token) = token {
+            return util::constant_time_eq(&token, &self.value.csrf_token);
+        }
+        req.uri().path().starts_with("/api/")
+            && req
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with("application/json"))
+    }
+}
+
+/// The list created on first start, given to the first user
+pub const DEFAULT_LIST_ID: ListId = ListId(1);
+
+/// Check that `user_id` has at least `required` access to `list_id`
+fn check_list_access(
+    list_access_table: &impl ReadableTable<(UserId, ListId), Access>,
+    user_id: UserId,
+    list_id: ListId,
+    required: Access,
+) -> anyhow::Result<Access> {
+    match list_access_table
+        .get((user_id, list_id))?
+        .map(|v| v.value())
+    {
+        Some(access) if required <= access => Ok(access),
+        Some(_) => Err(ForbiddenError("not allowed to change this list").into()),
+        None => Err(ForbiddenError("no access to this list").into()),
+    }
+}
+
+/// [`LIST_ACCESS_TABLE`] and its index [`LIST_USER_TABLE`], changed together
+pub struct ListAccessTables<'txn> {
+    access: Table<'txn, (UserId, ListId), Access>,
+    users: Table<'txn, (ListId, UserId), ()>,
+}
+
+impl<'txn> ListAccessTables<'txn> {
+    fn open(dbtx: &'txn WriteTransaction) -> anyhow::Result<Self> {
+        Ok(Self {
+            access: dbtx.open_table(LIST_ACCESS_TABLE)?,
+            users: dbtx.open_table(LIST_USER_TABLE)?,
+        })
+    }
+
+    fn insert(&mut self, user_id: UserId, list_id: ListId, access: Access) -> anyhow::Result<()> {
+        self.access.insert((user_id, list_id), access)?;
+        self.users.insert((list_id, user_id), ())?;
+        Ok(())
+    }
+
+    fn remove(&mut self, user_id: UserId, list_id: ListId) -> anyhow::Result<()> {
+        self.access.remove((user_id, list_id))?;
+        self.users.remove((list_id, user_id))?;
+        Ok(())
+    }
+}
+
+/// [`ITEM_ORDER_TABLE`] and [`ITEM_CHILD_ORDER_TABLE`], addressed by
+/// [`Siblings`]
+pub struct ItemOrderTables<'txn> {
+    list: MultimapTable<'txn, ListId, (SortId, ItemId)>,
+    children: MultimapTable<'txn, ItemId, (SortId, ItemId)>,
+}
+
+impl<'txn> ItemOrderTables<'txn> {
+    fn open(dbtx: &'txn WriteTransaction) -> anyhow::Result<Self> {
+        Ok(Self {
+            list: dbtx.open_multimap_table(ITEM_ORDER_TABLE)?,
+            children: dbtx.open_multimap_table(ITEM_CHILD_ORDER_TABLE)?,
+        })
+    }
+
+    /// `(SortId, ItemId)` of `siblings`, in order
+    fn get(&self, siblings: Siblings) -> anyhow::Result<MultimapValue<'_, (SortId, ItemId)>> {
+        Ok(match siblings {
+            Siblings::List(list_id) => self.list.get(list_id)?,
+            Siblings::Children(item_id) => self.children.get(item_id)?,
+        })
+    }
+
+    fn insert(&mut self, siblings: Siblings, entry: (SortId, ItemId)) -> anyhow::Result<()> {
+        match siblings {
+            Siblings::List(list_id) => self.list.insert(list_id, entry)?,
+            Siblings::Children(item_id) => self.children.insert(item_id, entry)?,
+        };
+        Ok(())
+    }
+
+    fn remove(&mut self, siblings: Siblings, entry: (SortId, ItemId)) -> anyhow::Result<()> {
+        match siblings {
+            Siblings::List(list_id) => self.list.remove(list_id, entry)?,
+            Siblings::Children(item_id) => self.children.remove(item_id, entry)?,
+        };
+        Ok(())
+    }
+
+    /// The item before `item_id` among `siblings`, `None` if it's the first
+    fn prev(&self, siblings: Siblings, item_id: ItemId) -> anyhow::Result<Option<ItemId>> {
+        let mut prev_id = None;
+        for entry in self.get(siblings)? {
+            let (_, id) = entry?.value();
+            if id == item_id {
+                break;
+            }
+            prev_id = Some(id);
+        }
+        Ok(prev_id)
+    }
+
+    /// Remove all of `siblings`, returning their `ItemId`s in order
+    fn remove_all(&mut self, siblings: Siblings) -> anyhow::Result<Vec<ItemId>> {
+        let entries = match siblings {
+            Siblings::List(list_id) => self.list.remove_all(list_id)?,
+            Siblings::Children(item_id) => self.children.remove_all(item_id)?,
+        };
+        Ok(entries
+            .map_ok(|entry| entry.value().1)
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+/// List and keyspace of items dropped between `prev` and `next`
+///
+/// That's where the neighbors are, which have to agree with `parent_id` and
+/// `list_id` if given. Without neighbors, it's the sub-items of `parent_id`,
+/// or the top level of `list_id`, or `default`.
+fn resolve_drop_target(
+    item_table: &Table<'_, ItemId, ItemValue>,
+    prev: Option<&ItemValue>,
+    next: Option<&ItemValue>,
+    parent_id: Option<ItemId>,
+    list_id: Option<ListId>,
+    default: (ListId, Siblings),
+) -> anyhow::Result<(ListId, Siblings)> {
+    if let (Some(prev), Some(next)) = (prev, next) {
+        if prev.siblings() != next.siblings() {
+            return Err(BadRequestError("prev_id and next_id elements not siblings").into());
+        }
+    }
+    let target = match (prev.or(next), parent_id, list_id) {
+        (Some(neighbor), _, _) => (neighbor.list_id, neighbor.siblings()),
+        (None, Some(parent_id), _) => {
+            let parent = item_table
+                .get(parent_id)?
+                .ok_or(NotFoundError("parent element"))?
+                .value();
+            (parent.list_id, Siblings::Children(parent_id))
+        }
+        (None, None, Some(list_id)) => (list_id, Siblings::List(list_id)),
+        (None, None, None) => default,
+    };
+    if parent_id.is_some_and(|parent_id| Siblings::Children(parent_id) != target.1) {
+        return Err(BadRequestError("prev_id/next_id element under a different parent").into());
+    }
+    if list_id.is_some_and(|list_id| list_id != target.0) {
+        return Err(BadRequestError("prev_id/next_id element in a different list").into());
+    }
+    Ok(target)
+}
+
+/// Reject moving `item_ids` under `parent_id` if that's one of them, or
+/// under one of them
+fn check_no_cycle(
+    item_table: &Table<'_, ItemId, ItemValue>,
+    item_ids: &[ItemId],
+    parent_id: ItemId,
+) -> anyhow::Result<()> {
+    let mut ancestor_id = Some(parent_id);
+    while let Some(id) = ancestor_id {
+        if item_ids.contains(&id) {
+            return Err(BadRequestError("can't move an item under itself").into());
+        }
+        ancestor_id = item_table
+            .get(id)?
+            .ok_or_else(|| format_err!("parent item not found"))?
+            .value()
+            .parent;
+    }
+    Ok(())
+}
+
+/// Update the search, tag and due date indexes for `item_id` changing
+/// from `old` to `new` data
+pub(crate) fn index_item(
+    dbtx: &WriteTransaction,
+    item_id: ItemId,
+    old: Option<&ItemData>,
+    new: Option<&ItemData>,
+) -> anyhow::Result<()> {
+    let mut item_term_table = dbtx.open_table(ITEM_TERM_TABLE)?;
+    for term in old.map(search::item_terms).unwrap_or_default().keys() {
+        item_term_table.remove((term.as_str(), item_id))?;
+    }
+    for (term, weight) in new.map(search::item_terms).unwrap_or_default() {
+        item_term_table.insert((term.as_str(), item_id), weight)?;
+    }
+    let mut item_tag_table = dbtx.open_table(ITEM_TAG_TABLE)?;
+    for tag in old.iter().flat_map(|data| &data.tags) {
+        item_tag_table.remove((tag.as_str(), item_id))?;
+    }
+    for tag in new.iter().flat_map(|data| &data.tags) {
+        item_tag_table.insert((tag.as_str(), item_id), ())?;
+    }
+    let mut item_due_table = dbtx.open_table(ITEM_DUE_TABLE)?;
+    if let Some(due) = old.and_then(|data| data.due.as_ref()) {
+        item_due_table.remove((due.as_str(), item_id))?;
+    }
+    if let Some(due) = new.and_then(|data| data.due.as_ref()) {
+        item_due_table.insert((due.as_str(), item_id), ())?;
+    }
+    Ok(())
+}
+
+/// Reject tags [`ItemData::parse_tags`] wouldn't have produced, and invalid
+/// due dates
+pub(crate) fn check_item_data(item_data: &ItemData) -> anyhow::Result<()> {
+    if ItemData::parse_tags(&item_data.tags.join(",")) != item_data.tags {
+        return Err(BadRequestError("empty, repeated or comma separated tags").into());
+    }
+    if item_data
+        .due
+        .as_ref()
+        .is_some_and(|due| !util::is_valid_date(due))
+    {
+        return Err(BadRequestError("due date not in YYYY-MM-DD format").into());
+    }
+    Ok(())
+}
+
+/// Lists, items and order entries, as [`fsck::find_problems`] takes them
+type FsckInput = (
+    HashSet<ListId>,
+    BTreeMap<ItemId, ItemValue>,
+    Vec<(Siblings, SortId, ItemId)>,
+);
+
+fn read_fsck_input(
+    list_table: &impl ReadableTable<ListId, ListValue>,
+    item_table: &impl ReadableTable<ItemId, ItemValue>,
+    item_order_table: &impl ReadableMultimapTable<ListId, (SortId, ItemId)>,
+    item_child_order_table: &impl ReadableMultimapTable<ItemId, (SortId, ItemId)>,
+) -> anyhow::Result<FsckInput> {
+    let list_ids = list_table
+        .iter()?
+        .map_ok(|(k, _)| k.value())
+        .collect::<Result<HashSet<_>, _>>()?;
+    let items = item_table
+        .iter()?
+        .map_ok(|(k, v)| (k.value(), v.value()))
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+    let mut entries = vec![];
+    for entry in item_order_table.iter()? {
+        let (list_id, values) = entry?;
+        for value in values {
+            let (sort_id, item_id) = value?.value();
+            entries.push((Siblings::List(list_id.value()), sort_id, item_id));
+        }
+    }
+    for entry in item_child_order_table.iter()? {
+        let (parent_id, values) = entry?;
+        for value in values {
+            let (sort_id, item_id) = value?.value();
+            entries.push((Siblings::Children(parent_id.value()), sort_id, item_id));
+        }
+    }
+    Ok((list_ids, items, entries))
+}
 
 #[derive(Clone)]
 pub struct Service {
     opts: opts::Opts,
-    _state: Arc<State>,
+    state: Arc<State>,
     db: Database,
     router_get: Router,
     router_post: Router,
+    router_put: Router,
+    router_delete: Router,
     pre_rate_limiter: pre::FastPreRateLimiter,
     rate_limiter: conventional::RateLimiter,
 }
 
 impl Service {
+    /// Open the database, creating or upgrading it as needed
     pub fn new(opts: opts::Opts) -> anyhow::Result<Self> {
+        let db = Database::open(&opts.db)?;
+        Self::with_db(opts, db)?.init_tables()
+    }
+
+    /// Open an existing database without changing it
+    ///
+    /// It has to be at the current version already.
+    pub fn open(opts: opts::Opts) -> anyhow::Result<Self> {
+        let db = Database::open_existing(&opts.db)?;
+        db.read_with(migrate::check_version)?;
+        Self::with_db(opts, db)
+    }
+
+    fn with_db(opts: opts::Opts, db: Database) -> anyhow::Result<Self> {
         let mut router_get = Router::new();
         let mut router_post = Router::new();
+        let mut router_put = Router::new();
+        let mut router_delete = Router::new();
         router_get.insert("/", Self::home)?;
-        router_post.insert("/item", Self::item_create)?;
+        router_get.insert("/login", Self::login_get)?;
+        router_post.insert("/login", Self::login_post)?;
+        router_post.insert("/logout", Self::logout)?;
+        router_post.insert("/list", Self::list_create)?;
+        router_get.insert("/list/:list_id", Self::list_get)?;
+        router_get.insert("/list/:list_id/share", Self::list_share_get)?;
+        router_post.insert("/list/:list_id/share", Self::list_share_post)?;
+        router_get.insert("/list/:list_id/events", Self::list_events)?;
+        router_get.insert("/list/:list_id/items", Self::list_items)?;
+        router_post.insert("/list/:list_id/item", Self::item_create)?;
+        router_get.insert("/list/:list_id/item/:id", Self::item_get)?;
         router_post.insert("/item/order", Self::item_order)?;
+        router_post.insert("/item/order/batch", Self::item_order_batch)?;
+        router_post.insert("/item/preview", Self::item_preview)?;
+        router_post.insert("/undo", Self::undo_last)?;
         router_get.insert("/item/:id", Self::item_get)?;
         router_post.insert("/item/:id", Self::item_update)?;
         router_get.insert("/item/:id/edit", Self::item_edit)?;
+        router_get.insert("/item/:id/history", Self::item_history)?;
+        router_post.insert("/item/:id/complete", Self::item_complete)?;
+        router_post.insert("/item/:id/delete", Self::item_delete)?;
+        router_get.insert("/search", Self::search)?;
+        router_get.insert("/upcoming", Self::upcoming)?;
+        router_get.insert("/trash", Self::trash)?;
+        router_get.insert("/backup", Self::backup)?;
+        router_get.insert("/backup/export", Self::backup_export)?;
+        router_post.insert("/backup/import", Self::backup_import)?;
+        router_post.insert("/trash/:id/restore", Self::trash_restore)?;
+        router_post.insert("/trash/:id/purge", Self::trash_purge)?;
+        router_post.insert("/api/v1/login", Self::api_login)?;
+        router_get.insert("/api/v1/list", Self::api_list_list)?;
+        router_post.insert("/api/v1/list", Self::api_list_create)?;
+        router_get.insert("/api/v1/list/:list_id/item", Self::api_item_list)?;
+        router_post.insert("/api/v1/list/:list_id/item", Self::api_item_create)?;
+        router_post.insert("/api/v1/item/order", Self::api_item_order)?;
+        router_post.insert("/api/v1/item/order/batch", Self::api_item_order_batch)?;
+        router_get.insert("/api/v1/item/:id", Self::api_item_get)?;
+        router_put.insert("/api/v1/item/:id", Self::api_item_update)?;
+        router_delete.insert("/api/v1/item/:id", Self::api_item_delete)?;
         router_get.insert("/favicon.ico", Self::favicon_ico)?;
         router_get.insert("/style.css", Self::style_css)?;
         router_get.insert("/script.js", Self::script_js)?;
 
-        Self {
-            _state: Default::default(),
-            db: Database::open(&opts.db)?,
+        Ok(Self {
+            state: Default::default(),
+            db,
             opts,
             router_get,
             router_post,
+            router_put,
+            router_delete,
             pre_rate_limiter: pre::FastPreRateLimiter::new(20, 60),
             rate_limiter: conventional::RateLimiter::new(60, 60),
-        }
-        .init_tables()
+        })
     }
 
-    fn route(&self, req: &mut astra::Request) -> astra::Response {
+    fn route(&self, req: &mut astra::Request, session: &Session) -> astra::Response {
         if self.opts.debug_delay {
             std::thread::sleep(Duration::from_millis(500));
         }
@@ -72,7 +537,9 @@ impl Service {
         let (handler, params) = match (match *req.method() {
             Method::GET => &self.router_get,
             Method::POST => &self.router_post,
-            _ => return routes::not_found_404(),
+            Method::PUT => &self.router_put,
+            Method::DELETE => &self.router_delete,
+            _ => return not_found(&path),
         })
         .at(&path)
         {
@@ -81,10 +548,36 @@ impl Service {
                 (value, params)
             }
             // Otherwise, return a 404
-            Err(_) => return routes::not_found_404(),
+            Err(_) => return not_found(&path),
+        };
+
+        let res = if session.value.user_id.is_none() && !PUBLIC_PATHS.contains(&path.as_str()) {
+            Err(UnauthorizedError.into())
+        } else if *req.method() != Method::GET && !session.is_csrf_safe(req) {
+            Err(ForbiddenError("missing or invalid CSRF token").into())
+        } else {
+            (handler)(self, req, &params, session)
         };
 
-        (handler)(self, req, &params).unwrap_or_else(|error| {
+        res.unwrap_or_else(|error| {
+            if path.starts_with("/api/") {
+                return api::error_response(error);
+            }
+            if error.is::<UnauthorizedError>() {
+                return routes::see_other(req, "/login");
+            }
+            if error.is::<ForbiddenError>() {
+                return routes::forbidden_403();
+            }
+            if error.is::<ConflictError>() {
+                return routes::conflict_409();
+            }
+            if error.is::<NotFoundError>() {
+                return routes::not_found_404();
+            }
+            if let Some(BadRequestError(message)) = error.downcast_ref() {
+                return routes::bad_request_400(message);
+            }
             warn!(%error, "Route handler error");
             routes::internal_error()
         })
@@ -93,24 +586,51 @@ impl Service {
     fn handle_session(
         &self,
         req: &mut astra::Request,
-        f: impl FnOnce(&mut astra::Request) -> astra::Response,
+        f: impl FnOnce(&mut astra::Request, &Session) -> astra::Response,
     ) -> astra::Response {
-        let mut session = None;
-        for (k, v) in RequestExt(req).iter_cookies() {
-            if k == "session" {
-                session = Some(v.to_owned());
+        let session_id = RequestExt(req)
+            .iter_cookies()
+            .find(|(k, _)| *k == SESSION_COOKIE)
+            .and_then(|(_, v)| SessionId::from_str(v).ok());
+
+        let session = if STATIC_PATHS.contains(&req.uri().path()) {
+            Session::new(None)
+        } else {
+            match self.load_session(session_id) {
+                Ok(session) => session,
+                Err(error) => {
+                    warn!(%error, "Session handling error");
+                    return routes::internal_error();
+                }
             }
-        }
-        let mut resp = f(req);
+        };
 
-        if session.is_none() {
-            resp.headers_mut().insert(
-                header::SET_COOKIE,
-                HeaderValue::from_str("session=booo").expect("can't fail"),
-            );
-        }
+        f(req, &session)
+    }
 
-        resp
+    pub fn session_cookie(&self, session: &Session) -> HeaderValue {
+        let max_age = session
+            .value
+            .expires_at
+            .saturating_sub(session.value.created_at);
+        let secure = if self.opts.insecure_cookies {
+            ""
+        } else {
+            "; Secure"
+        };
+        HeaderValue::from_str(&format!(
+            "{SESSION_COOKIE}={}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}",
+            session.id
+        ))
+        .expect("can't fail")
+    }
+
+    /// A cookie making the browser forget the session
+    pub fn session_cookie_removal(&self) -> HeaderValue {
+        HeaderValue::from_str(&format!(
+            "{SESSION_COOKIE}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax"
+        ))
+        .expect("can't fail")
     }
 
     fn handle_rate_limiting(
@@ -136,180 +656,1992 @@ impl Service {
 
     pub fn init_tables(self) -> anyhow::Result<Self> {
         self.db.write_with(|dbtx| {
+            migrate::run(dbtx)?;
+            let mut list_table = dbtx.open_table(LIST_TABLE)?;
+            if list_table.is_empty()? {
+                list_table.insert(
+                    DEFAULT_LIST_ID,
+                    ListValue {
+                        name: "Inbox".into(),
+                    },
+                )?;
+            }
             let _ = dbtx.open_table(ITEM_TABLE)?;
-            let _ = dbtx.open_table(ITEM_ORDER_TABLE)?;
+            let _ = dbtx.open_multimap_table(ITEM_ORDER_TABLE)?;
+            let _ = dbtx.open_multimap_table(ITEM_CHILD_ORDER_TABLE)?;
+            let _ = dbtx.open_table(ITEM_TRASH_TABLE)?;
+            let _ = dbtx.open_table(SESSION_TABLE)?;
+            let _ = dbtx.open_table(USER_TABLE)?;
+            let _ = dbtx.open_table(USER_NAME_TABLE)?;
+            let _ = dbtx.open_table(LIST_ACCESS_TABLE)?;
+            let _ = dbtx.open_table(LIST_USER_TABLE)?;
+            let _ = dbtx.open_table(ITEM_EVENT_TABLE)?;
+            let _ = dbtx.open_table(UNDO_TABLE)?;
+            let _ = dbtx.open_table(ITEM_TAG_TABLE)?;
+            let _ = dbtx.open_table(ITEM_DUE_TABLE)?;
+            let _ = dbtx.open_table(ITEM_TERM_TABLE)?;
             Ok(())
         })?;
 
         Ok(self)
     }
 
-    pub fn read_items(&self) -> anyhow::Result<Vec<Item>> {
-        let mut items = self.db.read_with(|dbtx| {
-            Ok(dbtx
-                .open_table(ITEM_TABLE)?
-                .iter()?
-                .map_ok(|(k, v)| (k.value(), v.value()))
-                .collect::<Result<Vec<_>, _>>()?)
-        })?;
+    /// The unexpired session of `session_id`, or a new one that isn't
+    /// stored yet
+    pub fn load_session(&self, session_id: Option<SessionId>) -> anyhow::Result<Session> {
+        let now = util::now_unix_secs();
 
-        items.sort_unstable_by(|a, b| a.1.sort_id.cmp(&b.1.sort_id));
+        if let Some(session_id) = session_id {
+            let existing = self.db.read_with(|dbtx| {
+                Ok(dbtx
+                    .open_table(SESSION_TABLE)?
+                    .get(session_id)?
+                    .map(|v| v.value()))
+            })?;
+            if let Some(value) = existing.filter(|value| now < value.expires_at) {
+                return Ok(Session {
+                    id: session_id,
+                    value,
+                    is_stored: true,
+                });
+            }
+        }
 
-        Ok(items
-            .into_iter()
-            .map(|(k, v)| Item {
-                id: k,
-                data: v.data,
-            })
-            .collect())
+        Ok(Session::new(None))
     }
 
-    pub fn get_last_item_id(
-        &self,
-        items_table: &Table<'_, ItemId, ItemValue>,
-    ) -> anyhow::Result<ItemId> {
-        Ok(if let Some(res) = items_table.iter()?.next_back() {
-            let res = res?;
-            res.0.value()
-        } else {
-            ItemId(0)
-        })
+    /// Store a new session, returning the cookie to set for it
+    pub fn store_session(&self, session: &Session) -> anyhow::Result<HeaderValue> {
+        self.db.write_with(|dbtx| {
+            dbtx.open_table(SESSION_TABLE)?
+                .insert(session.id, &session.value)?;
+            Ok(())
+        })?;
+        Ok(self.session_cookie(session))
     }
 
-    pub fn get_front_item_sort_id(
+    /// Replace the session `old_session_id` with a new one, logged in as
+    /// `user_id`
+    ///
+    /// The session id changes on login, so an id known before can't be used
+    /// to act as the user.
+    pub fn login_session(
         &self,
-        items_order_table: &Table<'_, SortId, ItemId>,
-    ) -> anyhow::Result<SortId> {
-        let existing_first = if let Some(existing_first) = items_order_table.iter()?.next() {
-            let existing_first = existing_first?;
-            Some(existing_first.0.value())
-        } else {
-            None
+        old_session_id: SessionId,
+        user_id: UserId,
+    ) -> anyhow::Result<Session> {
+        let session = Session {
+            is_stored: true,
+            ..Session::new(Some(user_id))
         };
-
-        Ok(SortId::in_front(existing_first.as_ref()))
+        self.db.write_with(|dbtx| {
+            let mut session_table = dbtx.open_table(SESSION_TABLE)?;
+            session_table.remove(old_session_id)?;
+            session_table.insert(session.id, &session.value)?;
+            Ok(())
+        })?;
+        self.state.broadcaster.close_session(old_session_id);
+        Ok(session)
     }
 
-    pub fn create_item(&self, item_data: ItemData) -> anyhow::Result<ItemId> {
+    pub fn delete_session(&self, session_id: SessionId) -> anyhow::Result<()> {
         self.db.write_with(|dbtx| {
-            let mut item_order_table = dbtx.open_table(ITEM_ORDER_TABLE)?;
-            let sort_id = self.get_front_item_sort_id(&item_order_table)?;
-
-            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
-            let item_id = self.get_last_item_id(&item_table)?.increment();
-            item_table.insert(
-                item_id,
-                ItemValue {
-                    sort_id: sort_id.clone(),
-                    data: item_data,
-                },
-            )?;
-            item_order_table.insert(sort_id, item_id)?;
-            Ok(item_id)
-        })
+            dbtx.open_table(SESSION_TABLE)?.remove(session_id)?;
+            Ok(())
+        })?;
+        self.state.broadcaster.close_session(session_id);
+        Ok(())
     }
 
-    pub fn change_item_order(
+    /// Create a user
+    ///
+    /// The first user created becomes the owner of all the lists created
+    /// before there were any users.
+    pub fn create_user(
         &self,
-        prev_id: Option<ItemId>,
-        curr_id: ItemId,
-        next_id: Option<ItemId>,
-    ) -> anyhow::Result<()> {
+        username: &str,
+        password: &str,
+        is_admin: bool,
+    ) -> anyhow::Result<UserId> {
+        if username.is_empty() {
+            return Err(BadRequestError("username can't be empty").into());
+        }
+        if password.is_empty() {
+            return Err(BadRequestError("password can't be empty").into());
+        }
+        let password_hash = util::hash_password(password)?;
         self.db.write_with(|dbtx| {
-            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
-            let curr = item_table
-                .get(curr_id)?
-                .ok_or_else(|| format_err!("curr_id element not found"))?
-                .value();
-
-            let curr_old_sort_id = curr.sort_id.clone();
-            let prev = if let Some(prev_id) = prev_id {
-                Some(
-                    item_table
-                        .get(prev_id)?
-                        .ok_or_else(|| format_err!("prev_id element not found"))?
-                        .value(),
-                )
-            } else {
-                None
-            };
-            let next = if let Some(next_id) = next_id {
-                Some(
-                    item_table
-                        .get(next_id)?
-                        .ok_or_else(|| format_err!("next_id element not found"))?
-                        .value(),
-                )
+            let mut user_table = dbtx.open_table(USER_TABLE)?;
+            let mut user_name_table = dbtx.open_table(USER_NAME_TABLE)?;
+            if user_name_table.get(username)?.is_some() {
+                return Err(BadRequestError("username already taken").into());
+            }
+            let user_id = if let Some(res) = user_table.iter()?.next_back() {
+                res?.0.value().increment()
             } else {
-                None
-            };
-
-            let curr_new_sort_id = match (
-                prev.as_ref().map(|p| &p.sort_id),
-                next.as_ref().map(|n| &n.sort_id),
-            ) {
-                (Some(prev), Some(next)) => SortId::between(prev, next),
-                (Some(prev), None) => SortId::at_the_end(Some(prev)),
-                (None, Some(next)) => SortId::in_front(Some(next)),
-                (None, None) => {
-                    /* nothing to do */
-                    return Ok(());
+                let mut list_access_tables = ListAccessTables::open(dbtx)?;
+                for entry in dbtx.open_table(LIST_TABLE)?.iter()? {
+                    list_access_tables.insert(UserId(1), entry?.0.value(), Access::Owner)?;
                 }
+                UserId(1)
             };
-
-            if curr_new_sort_id != curr_old_sort_id {
-                let mut item_order_table = dbtx.open_table(ITEM_ORDER_TABLE)?;
-                item_table.insert(
-                    curr_id,
-                    ItemValue {
-                        sort_id: curr_new_sort_id.clone(),
-                        ..curr
-                    },
-                )?;
-                item_order_table.remove(curr.sort_id)?;
-                item_order_table.insert(curr_new_sort_id, curr_id)?;
-            }
-            Ok(())
+            user_table.insert(
+                user_id,
+                UserValue {
+                    username: username.to_owned(),
+                    password_hash,
+                    is_admin,
+                    created_at: util::now_unix_secs(),
+                },
+            )?;
+            user_name_table.insert(username, user_id)?;
+            Ok(user_id)
         })
     }
 
-    pub fn load_item(&self, item_id: ItemId) -> anyhow::Result<ItemData> {
+    pub fn load_user(&self, user_id: UserId) -> anyhow::Result<Option<UserValue>> {
         self.db.read_with(|dbtx| {
-            let item_table = dbtx.open_table(ITEM_TABLE)?;
-            let item = item_table
-                .get(item_id)?
-                .ok_or_else(|| format_err!("item not found"))?
-                .value();
-
-            Ok(item.data)
+            Ok(dbtx
+                .open_table(USER_TABLE)?
+                .get(user_id)?
+                .map(|v| v.value()))
         })
     }
 
-    pub(crate) fn update_item(&self, item_id: ItemId, item_data: &ItemData) -> anyhow::Result<()> {
-        self.db.write_with(|dbtx| {
-            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
-
-            let item = item_table
-                .get(item_id)?
-                .ok_or_else(|| format_err!("item not found"))?
-                .value();
+    pub fn has_users(&self) -> anyhow::Result<bool> {
+        self.db
+            .read_with(|dbtx| Ok(!dbtx.open_table(USER_TABLE)?.is_empty()?))
+    }
 
-            item_table.insert(
-                item_id,
-                ItemValue {
-                    sort_id: item.sort_id,
-                    data: item_data.to_owned(),
-                },
-            )?;
+    /// Check `username` and `password`, returning the user they belong to
+    pub fn authenticate(&self, username: &str, password: &str) -> anyhow::Result<Option<UserId>> {
+        let user = self.db.read_with(|dbtx| {
+            let Some(user_id) = dbtx
+                .open_table(USER_NAME_TABLE)?
+                .get(username)?
+                .map(|v| v.value())
+            else {
+                return Ok(None);
+            };
+            Ok(dbtx
+                .open_table(USER_TABLE)?
+                .get(user_id)?
+                .map(|v| (user_id, v.value())))
+        })?;
 
-            Ok(())
-        })
+        Ok(user
+            .filter(|(_, user)| util::verify_password(password, &user.password_hash))
+            .map(|(user_id, _)| user_id))
     }
+
+    /// Delete all expired sessions, returning how many were deleted
+    pub fn purge_expired_sessions(&self) -> anyhow::Result<usize> {
+        let now = crate::util::now_unix_secs();
+        self.db.write_with(|dbtx| {
+            let mut session_table = dbtx.open_table(SESSION_TABLE)?;
+            let expired = session_table
+                .iter()?
+                .filter_map(|res| match res {
+                    Ok((k, v)) if v.value().expires_at <= now => Some(Ok(k.value())),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            for session_id in &expired {
+                session_table.remove(session_id)?;
+            }
+            Ok(expired.len())
+        })
+    }
+
+    /// Purge expired sessions hourly, for as long as the process runs
+    pub fn start_session_purge_thread(self) -> Self {
+        let service = self.clone();
+        std::thread::spawn(move || loop {
+            match service.purge_expired_sessions() {
+                Ok(count) => debug!(count, "Purged expired sessions"),
+                Err(error) => warn!(%error, "Failed to purge expired sessions"),
+            }
+            std::thread::sleep(Duration::from_secs(60 * 60));
+        });
+        self
+    }
+
+    /// Read lists `user_id` has access to
+    /// Ids of the lists `user_id` can change the items of
+    pub fn read_editable_list_ids(&self, user_id: UserId) -> anyhow::Result<HashSet<ListId>> {
+        Ok(self
+            .read_lists(user_id)?
+            .into_iter()
+            .filter(|(_, _, access)| Access::Edit <= *access)
+            .map(|(list_id, _, _)| list_id)
+            .collect())
+    }
+
+    pub fn read_lists(&self, user_id: UserId) -> anyhow::Result<Vec<(ListId, ListValue, Access)>> {
+        self.db.read_with(|dbtx| {
+            let list_table = dbtx.open_table(LIST_TABLE)?;
+            let mut lists = vec![];
+            for entry in dbtx
+                .open_table(LIST_ACCESS_TABLE)?
+                .range((user_id, ListId(0))..=(user_id, ListId(u64::MAX)))?
+            {
+                let (key, access) = entry?;
+                let (_, list_id) = key.value();
+                let list = list_table
+                    .get(list_id)?
+                    .ok_or_else(|| format_err!("list with access not found"))?
+                    .value();
+                lists.push((list_id, list, access.value()));
+            }
+            Ok(lists)
+        })
+    }
+
+    pub fn load_list(
+        &self,
+        user_id: UserId,
+        list_id: ListId,
+    ) -> anyhow::Result<(ListValue, Access)> {
+        self.db.read_with(|dbtx| {
+            let access = check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                list_id,
+                Access::Read,
+            )?;
+            let list = dbtx
+                .open_table(LIST_TABLE)?
+                .get(list_id)?
+                .ok_or(NotFoundError("list"))?
+                .value();
+            Ok((list, access))
+        })
+    }
+
+    /// Create a list owned by `user_id`
+    pub fn create_list(&self, user_id: UserId, list_value: ListValue) -> anyhow::Result<ListId> {
+        self.db.write_with(|dbtx| {
+            let mut list_table = dbtx.open_table(LIST_TABLE)?;
+            let list_id = if let Some(res) = list_table.iter()?.next_back() {
+                res?.0.value().increment()
+            } else {
+                DEFAULT_LIST_ID
+            };
+            list_table.insert(list_id, list_value)?;
+            ListAccessTables::open(dbtx)?.insert(user_id, list_id, Access::Owner)?;
+            Ok(list_id)
+        })
+    }
+
+    /// Give `user_id` an "Inbox" list if they have no list at all
+    ///
+    /// Done on login, so that just looking at the lists never writes.
+    pub fn create_inbox_if_missing(&self, user_id: UserId) -> anyhow::Result<()> {
+        if self.read_lists(user_id)?.is_empty() {
+            self.create_list(
+                user_id,
+                ListValue {
+                    name: "Inbox".into(),
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Read users `list_id` is shared with, including its owner
+    ///
+    /// Only the owner can see them.
+    pub fn read_list_shares(
+        &self,
+        user_id: UserId,
+        list_id: ListId,
+    ) -> anyhow::Result<Vec<(UserId, UserValue, Access)>> {
+        self.db.read_with(|dbtx| {
+            let list_access_table = dbtx.open_table(LIST_ACCESS_TABLE)?;
+            check_list_access(&list_access_table, user_id, list_id, Access::Owner)?;
+            let user_table = dbtx.open_table(USER_TABLE)?;
+            let mut shares = vec![];
+            for entry in dbtx
+                .open_table(LIST_USER_TABLE)?
+                .range((list_id, UserId(0))..=(list_id, UserId(u64::MAX)))?
+            {
+                let (_, share_user_id) = entry?.0.value();
+                let access = list_access_table
+                    .get((share_user_id, list_id))?
+                    .ok_or_else(|| format_err!("indexed list access not found"))?
+                    .value();
+                let user = user_table
+                    .get(share_user_id)?
+                    .ok_or_else(|| format_err!("user with access not found"))?
+                    .value();
+                shares.push((share_user_id, user, access));
+            }
+            Ok(shares)
+        })
+    }
+
+    /// Give `username` `access` to `list_id`, or take it away with `None`
+    pub fn share_list(
+        &self,
+        user_id: UserId,
+        list_id: ListId,
+        username: &str,
+        access: Option<Access>,
+    ) -> anyhow::Result<()> {
+        if access == Some(Access::Owner) {
+            return Err(BadRequestError("lists can't have more than one owner").into());
+        }
+        let share_user_id = self.db.write_with(|dbtx| {
+            let mut list_access_tables = ListAccessTables::open(dbtx)?;
+            check_list_access(&list_access_tables.access, user_id, list_id, Access::Owner)?;
+            let share_user_id = dbtx
+                .open_table(USER_NAME_TABLE)?
+                .get(username)?
+                .ok_or(NotFoundError("user"))?
+                .value();
+            if share_user_id == user_id {
+                return Err(BadRequestError("can't change own access").into());
+            }
+            match access {
+                Some(access) => list_access_tables.insert(share_user_id, list_id, access)?,
+                None => list_access_tables.remove(share_user_id, list_id)?,
+            }
+            Ok(share_user_id)
+        })?;
+        self.state.broadcaster.close_user(list_id, share_user_id);
+        Ok(())
+    }
+
+    /// Items in lists `user_id` can read that match all terms of `query`,
+    /// best matches first
+    ///
+    /// A query term also matches the terms it's a prefix of, at a lower
+    /// score than an exact match.
+    pub fn search_items(&self, user_id: UserId, query: &str) -> anyhow::Result<Vec<Item>> {
+        let terms = search::query_terms(query);
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+        self.db.read_with(|dbtx| {
+            let item_term_table = dbtx.open_table(ITEM_TERM_TABLE)?;
+            let mut scores: Option<HashMap<ItemId, u32>> = None;
+            for term in &terms {
+                let mut term_scores = HashMap::new();
+                for entry in item_term_table.range((term.as_str(), ItemId(0))..)? {
+                    let (key, weight) = entry?;
+                    let (indexed_term, item_id) = key.value();
+                    if !indexed_term.starts_with(term.as_str()) {
+                        break;
+                    }
+                    let score = if indexed_term == term {
+                        2 * weight.value()
+                    } else {
+                        weight.value()
+                    };
+                    let best = term_scores.entry(item_id).or_insert(0);
+                    *best = cmp::max(*best, score);
+                }
+                scores = Some(match scores {
+                    None => term_scores,
+                    Some(scores) => scores
+                        .into_iter()
+                        .filter_map(|(item_id, score)| {
+                            term_scores
+                                .get(&item_id)
+                                .map(|term_score| (item_id, score + term_score))
+                        })
+                        .collect(),
+                });
+            }
+
+            let item_table = dbtx.open_table(ITEM_TABLE)?;
+            let list_access_table = dbtx.open_table(LIST_ACCESS_TABLE)?;
+            let mut results = vec![];
+            for (item_id, score) in scores.unwrap_or_default() {
+                let item = item_table
+                    .get(item_id)?
+                    .ok_or_else(|| format_err!("indexed item not found"))?
+                    .value();
+                if list_access_table.get((user_id, item.list_id))?.is_none() {
+                    continue;
+                }
+                results.push((score, Item::from_value(item_id, item)));
+            }
+            results.sort_by(|(a_score, a), (b_score, b)| {
+                b_score.cmp(a_score).then_with(|| b.id.cmp(&a.id))
+            });
+            Ok(results
+                .into_iter()
+                .take(SEARCH_RESULTS_MAX)
+                .map(|(_, item)| item)
+                .collect())
+        })
+    }
+
+    /// Items not done yet that have a due date, in lists `user_id` can read,
+    /// soonest due first
+    pub fn read_upcoming_items(&self, user_id: UserId) -> anyhow::Result<Vec<Item>> {
+        self.db.read_with(|dbtx| {
+            let item_table = dbtx.open_table(ITEM_TABLE)?;
+            let list_access_table = dbtx.open_table(LIST_ACCESS_TABLE)?;
+
+            let mut items = vec![];
+            for entry in dbtx.open_table(ITEM_DUE_TABLE)?.iter()? {
+                let (_, item_id) = entry?.0.value();
+                let item = item_table
+                    .get(item_id)?
+                    .ok_or_else(|| format_err!("item with due date not found"))?
+                    .value();
+                if item.data.completed_at.is_some()
+                    || list_access_table.get((user_id, item.list_id))?.is_none()
+                {
+                    continue;
+                }
+                items.push(Item::from_value(item_id, item));
+            }
+            Ok(items)
+        })
+    }
+
+    /// Items of `list_id` tagged with `tag`, in list order
+    pub fn read_tagged_items(
+        &self,
+        user_id: UserId,
+        list_id: ListId,
+        tag: &str,
+    ) -> anyhow::Result<Vec<Item>> {
+        self.db.read_with(|dbtx| {
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                list_id,
+                Access::Read,
+            )?;
+            let item_table = dbtx.open_table(ITEM_TABLE)?;
+
+            let mut items = vec![];
+            for entry in dbtx
+                .open_table(ITEM_TAG_TABLE)?
+                .range((tag, ItemId(0))..=(tag, ItemId(u64::MAX)))?
+            {
+                let (_, item_id) = entry?.0.value();
+                let item = item_table
+                    .get(item_id)?
+                    .ok_or_else(|| format_err!("tagged item not found"))?
+                    .value();
+                if item.list_id == list_id {
+                    items.push((item.sort_id.clone(), item_id, item));
+                }
+            }
+            items.sort_by(|(a_sort_id, a_id, _), (b_sort_id, b_id, _)| {
+                (a_sort_id, a_id).cmp(&(b_sort_id, b_id))
+            });
+            Ok(items
+                .into_iter()
+                .map(|(_, item_id, item)| Item::from_value(item_id, item))
+                .collect())
+        })
+    }
+
+    /// Read items of a list, in order
+    ///
+    /// Sub-items come depth first, each parent right before its sub-items.
+    pub fn read_items(&self, user_id: UserId, list_id: ListId) -> anyhow::Result<Vec<Item>> {
+        self.db.read_with(|dbtx| {
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                list_id,
+                Access::Read,
+            )?;
+            let item_table = dbtx.open_table(ITEM_TABLE)?;
+            let item_order_table = dbtx.open_multimap_table(ITEM_ORDER_TABLE)?;
+            let item_child_order_table = dbtx.open_multimap_table(ITEM_CHILD_ORDER_TABLE)?;
+
+            let mut items = vec![];
+            let mut stack = vec![item_order_table.get(list_id)?];
+            while let Some(siblings) = stack.last_mut() {
+                let Some(entry) = siblings.next() else {
+                    stack.pop();
+                    continue;
+                };
+                let (_, item_id) = entry?.value();
+                let item = item_table
+                    .get(item_id)?
+                    .ok_or_else(|| format_err!("ordered item not found"))?
+                    .value();
+                items.push(Item::from_value(item_id, item));
+                stack.push(item_child_order_table.get(item_id)?);
+            }
+            Ok(items)
+        })
+    }
+
+    /// Allocate the id of a new item
+    ///
+    /// Without a stored last id, e.g. in a freshly imported database, it
+    /// falls back to the highest id any item, trashed item or event has.
+    fn next_item_id(&self, dbtx: &WriteTransaction) -> anyhow::Result<ItemId> {
+        let mut last_item_id_table = dbtx.open_table(LAST_ITEM_ID_TABLE)?;
+        let last_item_id = match last_item_id_table.get(())? {
+            Some(item_id) => item_id.value(),
+            None => {
+                let last_item = match dbtx.open_table(ITEM_TABLE)?.iter()?.next_back() {
+                    Some(res) => res?.0.value(),
+                    None => ItemId(0),
+                };
+                let last_trashed = match dbtx.open_table(ITEM_TRASH_TABLE)?.iter()?.next_back() {
+                    Some(res) => res?.0.value(),
+                    None => ItemId(0),
+                };
+                let last_event = match dbtx.open_table(ITEM_EVENT_TABLE)?.iter()?.next_back() {
+                    Some(res) => res?.0.value().0,
+                    None => ItemId(0),
+                };
+                cmp::max(last_item, cmp::max(last_trashed, last_event))
+            }
+        };
+        let item_id = last_item_id.increment();
+        last_item_id_table.insert((), item_id)?;
+        Ok(item_id)
+    }
+
+    pub fn get_front_item_sort_id(
+        &self,
+        item_order_tables: &ItemOrderTables<'_>,
+        siblings: Siblings,
+    ) -> anyhow::Result<SortId> {
+        let existing_first = if let Some(existing_first) = item_order_tables.get(siblings)?.next() {
+            let existing_first = existing_first?;
+            Some(existing_first.value().0)
+        } else {
+            None
+        };
+
+        Ok(SortId::in_front(existing_first.as_ref()))
+    }
+
+    /// Find a free `SortId` among `siblings`, as close to `sort_id` as
+    /// possible
+    ///
+    /// If `sort_id` is already taken, a `SortId` right after it is returned.
+    pub fn get_free_sort_id_near(
+        &self,
+        item_order_tables: &ItemOrderTables<'_>,
+        siblings: Siblings,
+        sort_id: SortId,
+    ) -> anyhow::Result<SortId> {
+        let mut taken = false;
+        for entry in item_order_tables.get(siblings)? {
+            let (existing, _) = entry?.value();
+            match existing.cmp(&sort_id) {
+                cmp::Ordering::Less => continue,
+                cmp::Ordering::Equal => taken = true,
+                cmp::Ordering::Greater if taken => return Ok(SortId::between(&sort_id, &existing)),
+                cmp::Ordering::Greater => break,
+            }
+        }
+
+        Ok(if taken {
+            SortId::at_the_end(Some(&sort_id))
+        } else {
+            sort_id
+        })
+    }
+
+    /// A free `SortId` right after `prev_id` among `siblings`, or in front
+    /// of them without `prev_id`
+    ///
+    /// `None` if `prev_id` isn't one of `siblings`.
+    fn get_sort_id_after(
+        &self,
+        item_order_tables: &ItemOrderTables<'_>,
+        siblings: Siblings,
+        prev_id: Option<ItemId>,
+    ) -> anyhow::Result<Option<SortId>> {
+        let Some(prev_id) = prev_id else {
+            return Ok(Some(
+                self.get_front_item_sort_id(item_order_tables, siblings)?,
+            ));
+        };
+        let mut entries = item_order_tables.get(siblings)?;
+        while let Some(entry) = entries.next() {
+            let (sort_id, item_id) = entry?.value();
+            if item_id == prev_id {
+                return Ok(Some(match entries.next().transpose()? {
+                    Some(next) => SortId::between(&sort_id, &next.value().0),
+                    None => SortId::at_the_end(Some(&sort_id)),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rebalance `siblings` if `new_sort_id` got longer than configured
+    pub fn maybe_rebalance_list(
+        &self,
+        item_table: &mut Table<'_, ItemId, ItemValue>,
+        item_order_tables: &mut ItemOrderTables<'_>,
+        siblings: Siblings,
+        new_sort_id: &SortId,
+    ) -> anyhow::Result<()> {
+        if self.opts.sort_id_rebalance_len < new_sort_id.as_bytes().len() {
+            self.rebalance_list(item_table, item_order_tables, siblings)?;
+        }
+        Ok(())
+    }
+
+    /// Reassign short, evenly spaced `SortId`s to all items of `siblings`
+    ///
+    /// Operates on already open tables, so it's part of the caller's
+    /// transaction.
+    pub fn rebalance_list(
+        &self,
+        item_table: &mut Table<'_, ItemId, ItemValue>,
+        item_order_tables: &mut ItemOrderTables<'_>,
+        siblings: Siblings,
+    ) -> anyhow::Result<()> {
+        let item_ids = item_order_tables.remove_all(siblings)?;
+
+        debug!(%siblings, len = item_ids.len(), "Rebalancing list");
+
+        for (item_id, sort_id) in item_ids
+            .iter()
+            .copied()
+            .zip(SortId::evenly_spaced(item_ids.len()))
+        {
+            let item = item_table
+                .get(item_id)?
+                .ok_or_else(|| format_err!("ordered item not found"))?
+                .value();
+            item_order_tables.insert(siblings, (sort_id.clone(), item_id))?;
+            item_table.insert(item_id, ItemValue { sort_id, ..item })?;
+        }
+        Ok(())
+    }
+
+    /// Set the list of the sub-items of `item_id`, recursively
+    fn set_children_list(
+        &self,
+        item_table: &mut Table<'_, ItemId, ItemValue>,
+        item_order_tables: &ItemOrderTables<'_>,
+        item_id: ItemId,
+        list_id: ListId,
+    ) -> anyhow::Result<()> {
+        for entry in item_order_tables.get(Siblings::Children(item_id))? {
+            let (_, child_id) = entry?.value();
+            let child = item_table
+                .get(child_id)?
+                .ok_or_else(|| format_err!("ordered item not found"))?
+                .value();
+            item_table.insert(child_id, ItemValue { list_id, ..child })?;
+            self.set_children_list(item_table, item_order_tables, child_id, list_id)?;
+        }
+        Ok(())
+    }
+
+    pub fn create_item(
+        &self,
+        user_id: UserId,
+        list_id: ListId,
+        item_data: ItemData,
+    ) -> anyhow::Result<ItemId> {
+        check_item_data(&item_data)?;
+        let item_id = self.db.write_with(|dbtx| {
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                list_id,
+                Access::Edit,
+            )?;
+            if dbtx.open_table(LIST_TABLE)?.get(list_id)?.is_none() {
+                return Err(NotFoundError("list").into());
+            }
+            let item_id = self.next_item_id(dbtx)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+            let siblings = Siblings::List(list_id);
+            let sort_id = self.get_front_item_sort_id(&item_order_tables, siblings)?;
+
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            self.record_item_event(
+                dbtx,
+                item_id,
+                user_id,
+                ItemEventKind::Create,
+                None,
+                Some(item_data.clone()),
+            )?;
+            self.set_undo(dbtx, user_id, vec![])?;
+            index_item(dbtx, item_id, None, Some(&item_data))?;
+            item_table.insert(
+                item_id,
+                ItemValue {
+                    list_id,
+                    sort_id: sort_id.clone(),
+                    data: item_data,
+                    revision: 0,
+                    parent: None,
+                },
+            )?;
+            item_order_tables.insert(siblings, (sort_id.clone(), item_id))?;
+            self.maybe_rebalance_list(&mut item_table, &mut item_order_tables, siblings, &sort_id)?;
+            Ok(item_id)
+        })?;
+        self.notify_list_changed(list_id);
+        Ok(item_id)
+    }
+
+    /// Move `curr_id` between `prev_id` and `next_id`
+    ///
+    /// Without neighbors given, the item is placed in front of the sub-items
+    /// of `parent_id` or of the top level of `list_id`, if given. Sub-items
+    /// move along with their parent.
+    pub fn change_item_order(
+        &self,
+        user_id: UserId,
+        prev_id: Option<ItemId>,
+        curr_id: ItemId,
+        next_id: Option<ItemId>,
+        list_id: Option<ListId>,
+        parent_id: Option<ItemId>,
+    ) -> anyhow::Result<()> {
+        let changed_list_ids = self.db.write_with(|dbtx| {
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+            let mut curr = item_table
+                .get(curr_id)?
+                .ok_or(NotFoundError("curr_id element"))?
+                .value();
+
+            let mut prev = if let Some(prev_id) = prev_id {
+                Some(
+                    item_table
+                        .get(prev_id)?
+                        .ok_or(NotFoundError("prev_id element"))?
+                        .value(),
+                )
+            } else {
+                None
+            };
+            let mut next = if let Some(next_id) = next_id {
+                Some(
+                    item_table
+                        .get(next_id)?
+                        .ok_or(NotFoundError("next_id element"))?
+                        .value(),
+                )
+            } else {
+                None
+            };
+
+            // duplicate `SortId`s leave no room between the neighbors,
+            // rebalancing their siblings gets rid of them
+            let crowded_siblings = match (&prev, &next) {
+                (Some(prev), Some(next)) if prev.sort_id == next.sort_id => Some(prev.siblings()),
+                _ => None,
+            };
+            if let Some(siblings) = crowded_siblings {
+                self.rebalance_list(&mut item_table, &mut item_order_tables, siblings)?;
+                for (item, item_id) in [(&mut prev, prev_id), (&mut next, next_id)] {
+                    if let Some(item_id) = item_id {
+                        *item = item_table.get(item_id)?.map(|item| item.value());
+                    }
+                }
+                curr = item_table
+                    .get(curr_id)?
+                    .ok_or(NotFoundError("curr_id element"))?
+                    .value();
+            }
+
+            let curr_old_sort_id = curr.sort_id.clone();
+            let curr_old_list_id = curr.list_id;
+            let curr_old_siblings = curr.siblings();
+            let (curr_new_list_id, curr_new_siblings) = resolve_drop_target(
+                &item_table,
+                prev.as_ref(),
+                next.as_ref(),
+                parent_id,
+                list_id,
+                (curr_old_list_id, curr_old_siblings),
+            )?;
+            let list_access_table = dbtx.open_table(LIST_ACCESS_TABLE)?;
+            for list_id in [curr_old_list_id, curr_new_list_id] {
+                check_list_access(&list_access_table, user_id, list_id, Access::Edit)?;
+            }
+            if curr_new_list_id != curr_old_list_id
+                && dbtx
+                    .open_table(LIST_TABLE)?
+                    .get(curr_new_list_id)?
+                    .is_none()
+            {
+                return Err(NotFoundError("list").into());
+            }
+            if let Siblings::Children(parent_id) = curr_new_siblings {
+                check_no_cycle(&item_table, &[curr_id], parent_id)?;
+            }
+
+            let curr_new_sort_id = match (
+                prev.as_ref().map(|p| &p.sort_id),
+                next.as_ref().map(|n| &n.sort_id),
+            ) {
+                (Some(prev), Some(next)) => SortId::between(prev, next),
+                (Some(prev), None) => SortId::at_the_end(Some(prev)),
+                (None, Some(next)) => SortId::in_front(Some(next)),
+                (None, None) if curr_new_siblings != curr_old_siblings => {
+                    self.get_front_item_sort_id(&item_order_tables, curr_new_siblings)?
+                }
+                (None, None) => {
+                    /* nothing to do */
+                    return Ok(vec![]);
+                }
+            };
+
+            if curr_new_sort_id != curr_old_sort_id || curr_new_siblings != curr_old_siblings {
+                let prev_id = item_order_tables.prev(curr_old_siblings, curr_id)?;
+                let seq = self.record_item_event(
+                    dbtx,
+                    curr_id,
+                    user_id,
+                    ItemEventKind::Reorder {
+                        from: (curr_old_list_id, curr.parent, curr_old_sort_id.clone()),
+                        to: (
+                            curr_new_list_id,
+                            curr_new_siblings.parent(),
+                            curr_new_sort_id.clone(),
+                        ),
+                    },
+                    Some(curr.data.clone()),
+                    Some(curr.data.clone()),
+                )?;
+                self.set_undo(
+                    dbtx,
+                    user_id,
+                    vec![UndoEvent {
+                        item_id: curr_id,
+                        seq,
+                        revision: curr.revision,
+                        prev_id,
+                    }],
+                )?;
+                item_order_tables.remove(curr_old_siblings, (curr_old_sort_id, curr_id))?;
+                item_order_tables.insert(curr_new_siblings, (curr_new_sort_id.clone(), curr_id))?;
+                item_table.insert(
+                    curr_id,
+                    ItemValue {
+                        list_id: curr_new_list_id,
+                        sort_id: curr_new_sort_id.clone(),
+                        parent: curr_new_siblings.parent(),
+                        ..curr
+                    },
+                )?;
+                if curr_new_list_id != curr_old_list_id {
+                    self.set_children_list(
+                        &mut item_table,
+                        &item_order_tables,
+                        curr_id,
+                        curr_new_list_id,
+                    )?;
+                }
+                self.maybe_rebalance_list(
+                    &mut item_table,
+                    &mut item_order_tables,
+                    curr_new_siblings,
+                    &curr_new_sort_id,
+                )?;
+            }
+            if curr_new_list_id == curr_old_list_id {
+                Ok(vec![curr_new_list_id])
+            } else {
+                Ok(vec![curr_old_list_id, curr_new_list_id])
+            }
+        })?;
+        for list_id in changed_list_ids {
+            self.notify_list_changed(list_id);
+        }
+        Ok(())
+    }
+
+    /// Move `item_ids`, in the given order, contiguously between `prev_id`
+    /// and `next_id`
+    ///
+    /// The target is where the anchors are, or the sub-items of `parent_id`,
+    /// or the top level of `list_id`, or where the first item is, in this
+    /// order. With no anchors given the items are placed in front of the
+    /// target.
+    pub fn change_items_order(
+        &self,
+        user_id: UserId,
+        prev_id: Option<ItemId>,
+        item_ids: &[ItemId],
+        next_id: Option<ItemId>,
+        list_id: Option<ListId>,
+        parent_id: Option<ItemId>,
+    ) -> anyhow::Result<()> {
+        let Some(first_id) = item_ids.first() else {
+            return Ok(());
+        };
+        if item_ids
+            .iter()
+            .any(|id| Some(*id) == prev_id || Some(*id) == next_id)
+        {
+            return Err(BadRequestError("prev_id/next_id can't be one of the moved items").into());
+        }
+        if item_ids.iter().collect::<HashSet<_>>().len() != item_ids.len() {
+            return Err(BadRequestError("duplicate moved items").into());
+        }
+
+        let changed_list_ids = self.db.write_with(|dbtx| {
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+
+            let mut prev = if let Some(prev_id) = prev_id {
+                Some(
+                    item_table
+                        .get(prev_id)?
+                        .ok_or(NotFoundError("prev_id element"))?
+                        .value(),
+                )
+            } else {
+                None
+            };
+            let mut next = if let Some(next_id) = next_id {
+                Some(
+                    item_table
+                        .get(next_id)?
+                        .ok_or(NotFoundError("next_id element"))?
+                        .value(),
+                )
+            } else {
+                None
+            };
+
+            let first = item_table
+                .get(first_id)?
+                .ok_or(NotFoundError("item"))?
+                .value();
+            let (target_list_id, target_siblings) = resolve_drop_target(
+                &item_table,
+                prev.as_ref(),
+                next.as_ref(),
+                parent_id,
+                list_id,
+                (first.list_id, first.siblings()),
+            )?;
+            let list_access_table = dbtx.open_table(LIST_ACCESS_TABLE)?;
+            check_list_access(&list_access_table, user_id, target_list_id, Access::Edit)?;
+            if dbtx.open_table(LIST_TABLE)?.get(target_list_id)?.is_none() {
+                return Err(NotFoundError("list").into());
+            }
+            if let Siblings::Children(parent_id) = target_siblings {
+                check_no_cycle(&item_table, item_ids, parent_id)?;
+            }
+
+            let mut changed_list_ids = HashSet::from([target_list_id]);
+            let mut items = Vec::with_capacity(item_ids.len());
+            for item_id in item_ids {
+                let item = item_table
+                    .get(item_id)?
+                    .ok_or(NotFoundError("item"))?
+                    .value();
+                check_list_access(&list_access_table, user_id, item.list_id, Access::Edit)?;
+                changed_list_ids.insert(item.list_id);
+                // before removing any, so moved neighbors are found as well
+                let prev_id = item_order_tables.prev(item.siblings(), *item_id)?;
+                items.push((*item_id, item, prev_id));
+            }
+            for (item_id, item, _) in &items {
+                item_order_tables.remove(item.siblings(), (item.sort_id.clone(), *item_id))?;
+            }
+
+            // duplicate `SortId`s leave no room between the neighbors,
+            // rebalancing their siblings gets rid of them
+            if matches!((&prev, &next), (Some(prev), Some(next)) if prev.sort_id == next.sort_id) {
+                self.rebalance_list(&mut item_table, &mut item_order_tables, target_siblings)?;
+                for (item, item_id) in [(&mut prev, prev_id), (&mut next, next_id)] {
+                    if let Some(item_id) = item_id {
+                        *item = item_table.get(item_id)?.map(|item| item.value());
+                    }
+                }
+            }
+
+            let sort_ids = match (prev, next) {
+                (None, None) => {
+                    let first = item_order_tables
+                        .get(target_siblings)?
+                        .next()
+                        .transpose()?
+                        .map(|entry| entry.value().0);
+                    SortId::n_between(None, first.as_ref(), items.len())
+                }
+                (prev, next) => SortId::n_between(
+                    prev.as_ref().map(|p| &p.sort_id),
+                    next.as_ref().map(|n| &n.sort_id),
+                    items.len(),
+                ),
+            };
+
+            let max_sort_id_len = sort_ids.iter().map(|s| s.as_bytes().len()).max();
+            let mut events = Vec::with_capacity(items.len());
+            for ((item_id, item, prev_id), sort_id) in items.into_iter().zip(sort_ids) {
+                let seq = self.record_item_event(
+                    dbtx,
+                    item_id,
+                    user_id,
+                    ItemEventKind::Reorder {
+                        from: (item.list_id, item.parent, item.sort_id.clone()),
+                        to: (target_list_id, target_siblings.parent(), sort_id.clone()),
+                    },
+                    Some(item.data.clone()),
+                    Some(item.data.clone()),
+                )?;
+                events.push(UndoEvent {
+                    item_id,
+                    seq,
+                    revision: item.revision,
+                    prev_id,
+                });
+                item_order_tables.insert(target_siblings, (sort_id.clone(), item_id))?;
+                let old_list_id = item.list_id;
+                item_table.insert(
+                    item_id,
+                    ItemValue {
+                        list_id: target_list_id,
+                        sort_id,
+                        parent: target_siblings.parent(),
+                        ..item
+                    },
+                )?;
+                if old_list_id != target_list_id {
+                    self.set_children_list(
+                        &mut item_table,
+                        &item_order_tables,
+                        item_id,
+                        target_list_id,
+                    )?;
+                }
+            }
+
+            self.set_undo(dbtx, user_id, events)?;
+
+            if max_sort_id_len.is_some_and(|len| self.opts.sort_id_rebalance_len < len) {
+                self.rebalance_list(&mut item_table, &mut item_order_tables, target_siblings)?;
+            }
+            Ok(changed_list_ids)
+        })?;
+        for list_id in changed_list_ids {
+            self.notify_list_changed(list_id);
+        }
+        Ok(())
+    }
+
+    /// Stream changes of the items of `list_id`, if the user of `session`
+    /// can read it
+    ///
+    /// The stream ends when the session is logged out or the user's access
+    /// to the list changes.
+    pub fn subscribe_list(
+        &self,
+        session: &Session,
+        list_id: ListId,
+    ) -> anyhow::Result<sse::EventStream> {
+        let user_id = session.user_id()?;
+        let (_, access) = self.load_list(user_id, list_id)?;
+        Ok(self
+            .state
+            .broadcaster
+            .subscribe(list_id, user_id, session.id, Access::Edit <= access))
+    }
+
+    /// Tell subscribers of `list_id` to reload its items
+    fn notify_list_changed(&self, list_id: ListId) {
+        self.state
+            .broadcaster
+            .send(list_id, "list", |_| "changed".to_owned());
+    }
+
+    /// Send the updated row of `item` to subscribers of its list
+    fn notify_item_changed(&self, item: &Item) {
+        self.state
+            .broadcaster
+            .send(item.list_id, &item.id.to_string(), |editable| {
+                item.item_line(editable).into_string()
+            });
+    }
+
+    pub fn load_item(&self, user_id: UserId, item_id: ItemId) -> anyhow::Result<ItemValue> {
+        self.db.read_with(|dbtx| {
+            let item_table = dbtx.open_table(ITEM_TABLE)?;
+            let item = item_table
+                .get(item_id)?
+                .ok_or(NotFoundError("item"))?
+                .value();
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                item.list_id,
+                Access::Read,
+            )?;
+
+            Ok(item)
+        })
+    }
+
+    /// Replace the data of an item, apart from whether it's done
+    ///
+    /// With `revision` given, the update is rejected with [`ConflictError`]
+    /// if the item was changed since that revision.
+    pub(crate) fn update_item(
+        &self,
+        user_id: UserId,
+        item_id: ItemId,
+        item_data: &ItemData,
+        revision: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.change_item_data(user_id, item_id, revision, |current| ItemData {
+            completed_at: current.completed_at,
+            ..item_data.clone()
+        })
+    }
+
+    /// Mark `item_id` done, or not done
+    pub fn complete_item(
+        &self,
+        user_id: UserId,
+        item_id: ItemId,
+        completed: bool,
+    ) -> anyhow::Result<()> {
+        let item = self.load_item(user_id, item_id)?;
+        if item.data.completed_at.is_some() == completed {
+            return Ok(());
+        }
+        self.change_item_data(user_id, item_id, Some(item.revision), |current| ItemData {
+            completed_at: completed.then(util::now_unix_secs),
+            ..current.clone()
+        })
+    }
+
+    /// Replace the data of an item with what `change` makes of the current
+    /// one
+    pub(crate) fn change_item_data(
+        &self,
+        user_id: UserId,
+        item_id: ItemId,
+        revision: Option<u64>,
+        change: impl FnOnce(&ItemData) -> ItemData,
+    ) -> anyhow::Result<()> {
+        let item = self.db.write_with(|dbtx| {
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+
+            let item = item_table
+                .get(item_id)?
+                .ok_or(NotFoundError("item"))?
+                .value();
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                item.list_id,
+                Access::Edit,
+            )?;
+            if revision.is_some_and(|revision| revision != item.revision) {
+                return Err(ConflictError.into());
+            }
+            let item_data = change(&item.data);
+            check_item_data(&item_data)?;
+
+            let seq = self.record_item_event(
+                dbtx,
+                item_id,
+                user_id,
+                ItemEventKind::Update,
+                Some(item.data.clone()),
+                Some(item_data.clone()),
+            )?;
+            self.set_undo(
+                dbtx,
+                user_id,
+                vec![UndoEvent {
+                    item_id,
+                    seq,
+                    revision: item.revision + 1,
+                    prev_id: None,
+                }],
+            )?;
+            index_item(dbtx, item_id, Some(&item.data), Some(&item_data))?;
+            let item = ItemValue {
+                data: item_data,
+                revision: item.revision + 1,
+                ..item
+            };
+            item_table.insert(item_id, &item)?;
+
+            Ok(item)
+        })?;
+        self.notify_item_changed(&Item::from_value(item_id, item));
+        Ok(())
+    }
+
+    /// Move an item to the trash, removing it from its list
+    ///
+    /// Its sub-items go to the trash along with it, see
+    /// [`Service::restore_item`]. Returns the `ListId` the item was removed
+    /// from.
+    pub fn delete_item(&self, user_id: UserId, item_id: ItemId) -> anyhow::Result<ListId> {
+        let list_id = self.db.write_with(|dbtx| {
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+            let mut item_trash_table = dbtx.open_table(ITEM_TRASH_TABLE)?;
+
+            let item = item_table
+                .get(item_id)?
+                .ok_or(NotFoundError("item"))?
+                .value();
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                item.list_id,
+                Access::Edit,
+            )?;
+            self.set_undo(dbtx, user_id, vec![])?;
+
+            let deleted_at = util::now_unix_secs();
+            let mut item_ids = vec![item_id];
+            let mut i = 0;
+            while let Some(&item_id) = item_ids.get(i) {
+                for entry in item_order_tables.get(Siblings::Children(item_id))? {
+                    item_ids.push(entry?.value().1);
+                }
+                i += 1;
+            }
+            for item_id in item_ids {
+                let item = item_table
+                    .remove(item_id)?
+                    .ok_or(NotFoundError("item"))?
+                    .value();
+                self.record_item_event(
+                    dbtx,
+                    item_id,
+                    user_id,
+                    ItemEventKind::Delete,
+                    Some(item.data.clone()),
+                    None,
+                )?;
+                index_item(dbtx, item_id, Some(&item.data), None)?;
+                item_order_tables.remove(item.siblings(), (item.sort_id.clone(), item_id))?;
+                item_trash_table.insert(
+                    item_id,
+                    TrashedItem {
+                        value: item,
+                        deleted_at,
+                    },
+                )?;
+            }
+            Ok(item.list_id)
+        })?;
+        self.notify_list_changed(list_id);
+        Ok(list_id)
+    }
+
+    /// Append a change of `item_id` made by `actor` to its history
+    ///
+    /// Returns the sequence number of the event.
+    fn record_item_event(
+        &self,
+        dbtx: &WriteTransaction,
+        item_id: ItemId,
+        actor: UserId,
+        kind: ItemEventKind,
+        before: Option<ItemData>,
+        after: Option<ItemData>,
+    ) -> anyhow::Result<u64> {
+        let mut item_event_table = dbtx.open_table(ITEM_EVENT_TABLE)?;
+        let seq = match item_event_table
+            .range((item_id, 0)..=(item_id, u64::MAX))?
+            .next_back()
+        {
+            Some(res) => res?.0.value().1 + 1,
+            None => 0,
+        };
+        item_event_table.insert(
+            (item_id, seq),
+            ItemEvent {
+                at: util::now_unix_secs(),
+                actor,
+                kind,
+                before,
+                after,
+            },
+        )?;
+        Ok(seq)
+    }
+
+    /// Make `events` the change [`Self::undo`] of `user_id` reverts
+    ///
+    /// Changes that can't be undone pass no `events`, so an older change
+    /// doesn't get undone instead.
+    fn set_undo(
+        &self,
+        dbtx: &WriteTransaction,
+        user_id: UserId,
+        events: Vec<UndoEvent>,
+    ) -> anyhow::Result<()> {
+        let mut undo_table = dbtx.open_table(UNDO_TABLE)?;
+        if events.is_empty() {
+            undo_table.remove(user_id)?;
+        } else {
+            undo_table.insert(user_id, UndoValue { events })?;
+        }
+        Ok(())
+    }
+
+    /// Revert the most recent edit or reorder of `user_id`
+    ///
+    /// Returns the list the reverted items are in, and for an edit, the
+    /// restored item.
+    pub fn undo(&self, user_id: UserId) -> anyhow::Result<(ListId, Option<(ItemId, ItemValue)>)> {
+        let (list_id, restored) = self.db.write_with(|dbtx| {
+            let UndoValue { events } = dbtx
+                .open_table(UNDO_TABLE)?
+                .remove(user_id)?
+                .ok_or(NotFoundError("change to undo"))?
+                .value();
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+            let list_access_table = dbtx.open_table(LIST_ACCESS_TABLE)?;
+
+            let item_event_table = dbtx.open_table(ITEM_EVENT_TABLE)?;
+            let mut events = events
+                .into_iter()
+                .map(|undo_event| {
+                    let event = item_event_table
+                        .get((undo_event.item_id, undo_event.seq))?
+                        .ok_or_else(|| format_err!("undo event not found"))?
+                        .value();
+                    Ok((undo_event, event))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            drop(item_event_table);
+            // moved items go back in their old order, so the neighbor each is
+            // put after is already back in place
+            events.sort_by_key(|(_, event)| match &event.kind {
+                ItemEventKind::Reorder { from, .. } => Some(from.2.clone()),
+                _ => None,
+            });
+
+            let mut list_id = None;
+            let mut restored = None;
+            // rebalancing is left for the end, not to move the keyspace under the
+            // items still to restore
+            let mut restored_sort_ids = vec![];
+            for (
+                UndoEvent {
+                    item_id,
+                    revision,
+                    prev_id,
+                    ..
+                },
+                event,
+            ) in events
+            {
+                let item = item_table
+                    .get(item_id)?
+                    .ok_or(NotFoundError("item"))?
+                    .value();
+                check_list_access(&list_access_table, user_id, item.list_id, Access::Edit)?;
+
+                match event.kind {
+                    ItemEventKind::Update => {
+                        if item.revision != revision {
+                            return Err(ConflictError.into());
+                        }
+                        let data = event
+                            .before
+                            .ok_or_else(|| format_err!("update event without data"))?;
+                        self.record_item_event(
+                            dbtx,
+                            item_id,
+                            user_id,
+                            ItemEventKind::Update,
+                            Some(item.data.clone()),
+                            Some(data.clone()),
+                        )?;
+                        index_item(dbtx, item_id, Some(&item.data), Some(&data))?;
+                        list_id = Some(item.list_id);
+                        let item = ItemValue {
+                            data,
+                            revision: item.revision + 1,
+                            ..item
+                        };
+                        item_table.insert(item_id, &item)?;
+                        restored = Some((item_id, item));
+                    }
+                    ItemEventKind::Reorder {
+                        from: (from_list_id, from_parent_id, from_sort_id),
+                        ..
+                    } => {
+                        // the old parent might be gone, then the item goes to the
+                        // top level of its old list
+                        let from_parent = match from_parent_id {
+                            Some(parent_id) => item_table
+                                .get(parent_id)?
+                                .map(|parent| (parent_id, parent.value().list_id)),
+                            None => None,
+                        };
+                        let (from_list_id, from_siblings) = match from_parent {
+                            Some((parent_id, parent_list_id)) => {
+                                check_no_cycle(&item_table, &[item_id], parent_id)?;
+                                (parent_list_id, Siblings::Children(parent_id))
+                            }
+                            None => (from_list_id, Siblings::List(from_list_id)),
+                        };
+                        check_list_access(&list_access_table, user_id, from_list_id, Access::Edit)?;
+                        item_order_tables
+                            .remove(item.siblings(), (item.sort_id.clone(), item_id))?;
+                        let sort_id = match self.get_sort_id_after(
+                            &item_order_tables,
+                            from_siblings,
+                            prev_id,
+                        )? {
+                            Some(sort_id) => sort_id,
+                            // the neighbor is gone from there, the old `SortId` is
+                            // the best guess left
+                            None => self.get_free_sort_id_near(
+                                &item_order_tables,
+                                from_siblings,
+                                from_sort_id,
+                            )?,
+                        };
+                        self.record_item_event(
+                            dbtx,
+                            item_id,
+                            user_id,
+                            ItemEventKind::Reorder {
+                                from: (item.list_id, item.parent, item.sort_id.clone()),
+                                to: (from_list_id, from_siblings.parent(), sort_id.clone()),
+                            },
+                            Some(item.data.clone()),
+                            Some(item.data.clone()),
+                        )?;
+                        item_order_tables.insert(from_siblings, (sort_id.clone(), item_id))?;
+                        let old_list_id = item.list_id;
+                        item_table.insert(
+                            item_id,
+                            ItemValue {
+                                list_id: from_list_id,
+                                sort_id: sort_id.clone(),
+                                parent: from_siblings.parent(),
+                                ..item
+                            },
+                        )?;
+                        if old_list_id != from_list_id {
+                            self.set_children_list(
+                                &mut item_table,
+                                &item_order_tables,
+                                item_id,
+                                from_list_id,
+                            )?;
+                        }
+                        list_id = Some(from_list_id);
+                        restored_sort_ids.push((from_siblings, sort_id));
+                    }
+                    _ => return Err(format_err!("change can't be undone")),
+                }
+            }
+
+            for (siblings, sort_id) in restored_sort_ids {
+                self.maybe_rebalance_list(
+                    &mut item_table,
+                    &mut item_order_tables,
+                    siblings,
+                    &sort_id,
+                )?;
+            }
+            let list_id = list_id.ok_or_else(|| format_err!("empty change to undo"))?;
+            Ok((list_id, restored))
+        })?;
+        self.notify_list_changed(list_id);
+        Ok((list_id, restored))
+    }
+
+    /// Read the history of an item, oldest change first
+    ///
+    /// Works for items in the trash too.
+    pub fn read_item_history(
+        &self,
+        user_id: UserId,
+        item_id: ItemId,
+    ) -> anyhow::Result<Vec<ItemEvent>> {
+        self.db.read_with(|dbtx| {
+            let list_id = match dbtx.open_table(ITEM_TABLE)?.get(item_id)? {
+                Some(item) => item.value().list_id,
+                None => {
+                    dbtx.open_table(ITEM_TRASH_TABLE)?
+                        .get(item_id)?
+                        .ok_or(NotFoundError("item"))?
+                        .value()
+                        .value
+                        .list_id
+                }
+            };
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                list_id,
+                Access::Read,
+            )?;
+            Ok(dbtx
+                .open_table(ITEM_EVENT_TABLE)?
+                .range((item_id, 0)..=(item_id, u64::MAX))?
+                .map_ok(|(_, v)| v.value())
+                .collect::<Result<Vec<_>, _>>()?)
+        })
+    }
+
+    /// Read items in the trash from lists `user_id` can edit, most recently
+    /// deleted first
+    pub fn read_trash(&self, user_id: UserId) -> anyhow::Result<Vec<(ItemId, TrashedItem)>> {
+        let mut items = self.db.read_with(|dbtx| {
+            let list_access_table = dbtx.open_table(LIST_ACCESS_TABLE)?;
+            let mut items = vec![];
+            for entry in dbtx.open_table(ITEM_TRASH_TABLE)?.iter()? {
+                let (k, v) = entry?;
+                let trashed = v.value();
+                if check_list_access(
+                    &list_access_table,
+                    user_id,
+                    trashed.value.list_id,
+                    Access::Edit,
+                )
+                .is_ok()
+                {
+                    items.push((k.value(), trashed));
+                }
+            }
+            Ok(items)
+        })?;
+
+        items.sort_unstable_by_key(|(_, trashed)| cmp::Reverse(trashed.deleted_at));
+
+        Ok(items)
+    }
+
+    /// Restore an item from the trash at its old position in its list
+    ///
+    /// If the old `SortId` got taken in the meantime, the item is placed
+    /// right after the item occupying it. If its parent is gone, it's restored
+    /// at the top level of its list.
+    pub fn restore_item(&self, user_id: UserId, item_id: ItemId) -> anyhow::Result<()> {
+        let list_id = self.db.write_with(|dbtx| {
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+            let mut item_trash_table = dbtx.open_table(ITEM_TRASH_TABLE)?;
+
+            let TrashedItem {
+                mut value,
+                deleted_at,
+            } = item_trash_table
+                .get(item_id)?
+                .ok_or(NotFoundError("trashed item"))?
+                .value();
+            if let Some(parent_id) = value.parent {
+                match item_table.get(parent_id)? {
+                    Some(parent) => value.list_id = parent.value().list_id,
+                    None => value.parent = None,
+                }
+            }
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                value.list_id,
+                Access::Edit,
+            )?;
+            self.set_undo(dbtx, user_id, vec![])?;
+
+            // sub-items deleted along with it come back too, they are the ones
+            // trashed at the same time
+            let list_id = value.list_id;
+            let mut restore = vec![(item_id, value)];
+            while let Some((item_id, value)) = restore.pop() {
+                item_trash_table.remove(item_id)?;
+                self.record_item_event(
+                    dbtx,
+                    item_id,
+                    user_id,
+                    ItemEventKind::Restore,
+                    None,
+                    Some(value.data.clone()),
+                )?;
+                index_item(dbtx, item_id, None, Some(&value.data))?;
+
+                let siblings = value.siblings();
+                let sort_id =
+                    self.get_free_sort_id_near(&item_order_tables, siblings, value.sort_id)?;
+
+                item_order_tables.insert(siblings, (sort_id.clone(), item_id))?;
+                item_table.insert(
+                    item_id,
+                    ItemValue {
+                        list_id,
+                        sort_id: sort_id.clone(),
+                        data: value.data,
+                        revision: value.revision,
+                        parent: value.parent,
+                    },
+                )?;
+                self.maybe_rebalance_list(
+                    &mut item_table,
+                    &mut item_order_tables,
+                    siblings,
+                    &sort_id,
+                )?;
+
+                for entry in item_trash_table.iter()? {
+                    let (child_id, child) = entry?;
+                    let child = child.value();
+                    if child.value.parent == Some(item_id) && child.deleted_at == deleted_at {
+                        restore.push((child_id.value(), child.value));
+                    }
+                }
+            }
+            Ok(list_id)
+        })?;
+        self.notify_list_changed(list_id);
+        Ok(())
+    }
+
+    /// Permanently remove an item from the trash
+    pub fn purge_item(&self, user_id: UserId, item_id: ItemId) -> anyhow::Result<()> {
+        self.db.write_with(|dbtx| {
+            let mut item_trash_table = dbtx.open_table(ITEM_TRASH_TABLE)?;
+            let trashed = item_trash_table
+                .get(item_id)?
+                .ok_or(NotFoundError("trashed item"))?
+                .value();
+            check_list_access(
+                &dbtx.open_table(LIST_ACCESS_TABLE)?,
+                user_id,
+                trashed.value.list_id,
+                Access::Edit,
+            )?;
+            item_trash_table.remove(item_id)?;
+            Ok(())
+        })
+    }
+
+    /// Reassign `SortId`s in every list and among the sub-items of every item
+    ///
+    /// Returns the number of rebalanced keyspaces.
+    pub fn rebalance_all(&self) -> anyhow::Result<usize> {
+        self.db.write_with(|dbtx| {
+            let list_ids = dbtx
+                .open_table(LIST_TABLE)?
+                .iter()?
+                .map_ok(|(k, _)| k.value())
+                .collect::<Result<Vec<_>, _>>()?;
+            let parent_ids = dbtx
+                .open_multimap_table(ITEM_CHILD_ORDER_TABLE)?
+                .iter()?
+                .map_ok(|(k, _)| k.value())
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+            let all_siblings: Vec<_> = list_ids
+                .into_iter()
+                .map(Siblings::List)
+                .chain(parent_ids.into_iter().map(Siblings::Children))
+                .collect();
+            for siblings in &all_siblings {
+                self.rebalance_list(&mut item_table, &mut item_order_tables, *siblings)?;
+            }
+            Ok(all_siblings.len())
+        })
+    }
+
+    /// Check [`ITEM_TABLE`] against the order tables
+    ///
+    /// With `repair`, dangling parents and lists are fixed, the order tables
+    /// are rebuilt from the items, and keyspaces with duplicate `SortId`s are
+    /// rebalanced. Returns the problems found before repairing.
+    pub fn fsck(&self, repair: bool) -> anyhow::Result<Vec<fsck::Problem>> {
+        if !repair {
+            return self.db.read_with(|dbtx| {
+                let (list_ids, items, entries) = read_fsck_input(
+                    &dbtx.open_table(LIST_TABLE)?,
+                    &dbtx.open_table(ITEM_TABLE)?,
+                    &dbtx.open_multimap_table(ITEM_ORDER_TABLE)?,
+                    &dbtx.open_multimap_table(ITEM_CHILD_ORDER_TABLE)?,
+                )?;
+                Ok(fsck::find_problems(&list_ids, &items, &entries))
+            });
+        }
+        self.db.write_with(|dbtx| {
+            let (list_ids, mut items, entries) = read_fsck_input(
+                &dbtx.open_table(LIST_TABLE)?,
+                &dbtx.open_table(ITEM_TABLE)?,
+                &dbtx.open_multimap_table(ITEM_ORDER_TABLE)?,
+                &dbtx.open_multimap_table(ITEM_CHILD_ORDER_TABLE)?,
+            )?;
+            let problems = fsck::find_problems(&list_ids, &items, &entries);
+            if problems.is_empty() {
+                return Ok(problems);
+            }
+
+            let mut list_table = dbtx.open_table(LIST_TABLE)?;
+            let mut list_access_tables = ListAccessTables::open(dbtx)?;
+            let admin_ids = dbtx
+                .open_table(USER_TABLE)?
+                .iter()?
+                .filter_map(|entry| match entry {
+                    Ok((k, v)) => v.value().is_admin.then(|| Ok(k.value())),
+                    Err(e) => Some(Err(e)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            for problem in &problems {
+                match problem {
+                    fsck::Problem::BadParent { item_id } => {
+                        if let Some(item) = items.get_mut(item_id) {
+                            item.parent = None;
+                        }
+                    }
+                    fsck::Problem::MissingList { list_id, .. }
+                        if list_table.get(list_id)?.is_none() =>
+                    {
+                        // only admins get to see what was in it
+                        list_table.insert(
+                            list_id,
+                            ListValue {
+                                name: "Recovered".into(),
+                            },
+                        )?;
+                        for admin_id in &admin_ids {
+                            list_access_tables.insert(*admin_id, *list_id, Access::Owner)?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            dbtx.delete_multimap_table(ITEM_ORDER_TABLE)?;
+            dbtx.delete_multimap_table(ITEM_CHILD_ORDER_TABLE)?;
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+            for (item_id, item) in &items {
+                item_order_tables.insert(item.siblings(), (item.sort_id.clone(), *item_id))?;
+                item_table.insert(item_id, item)?;
+            }
+            for (siblings, _) in fsck::duplicate_sort_ids(&items) {
+                self.rebalance_list(&mut item_table, &mut item_order_tables, siblings)?;
+            }
+
+            Ok(problems)
+        })
+    }
+
+    /// See [`Database::check_integrity`]
+    pub fn check_integrity(&mut self) -> anyhow::Result<bool> {
+        self.db.check_integrity()
+    }
+
+    /// See [`Database::compact`]
+    pub fn compact(&mut self) -> anyhow::Result<()> {
+        self.db.compact()
+    }
+
+    /// Reject users that aren't admins
+    pub fn check_admin(&self, user_id: UserId) -> anyhow::Result<()> {
+        match self.load_user(user_id)? {
+            Some(user) if user.is_admin => Ok(()),
+            _ => Err(ForbiddenError("only admins can do this").into()),
+        }
+    }
+
+    /// Dump all users, lists and items, items in list order
+    pub fn export_db(&self) -> anyhow::Result<Export> {
+        self.db.read_with(|dbtx| {
+            let mut users = vec![];
+            for entry in dbtx.open_table(USER_TABLE)?.iter()? {
+                let (user_id, user) = entry?;
+                users.push(ExportUser {
+                    id: user_id.value(),
+                    value: user.value(),
+                });
+            }
+
+            let mut shares: HashMap<ListId, Vec<ExportShare>> = HashMap::new();
+            for entry in dbtx.open_table(LIST_ACCESS_TABLE)?.iter()? {
+                let (key, access) = entry?;
+                let (user, list_id) = key.value();
+                shares.entry(list_id).or_default().push(ExportShare {
+                    user,
+                    access: access.value(),
+                });
+            }
+
+            let item_table = dbtx.open_table(ITEM_TABLE)?;
+            let item_order_table = dbtx.open_multimap_table(ITEM_ORDER_TABLE)?;
+            let item_child_order_table = dbtx.open_multimap_table(ITEM_CHILD_ORDER_TABLE)?;
+            let mut lists = vec![];
+            let mut items = vec![];
+            for entry in dbtx.open_table(LIST_TABLE)?.iter()? {
+                let (list_id, list) = entry?;
+                let list_id = list_id.value();
+                lists.push(ExportList {
+                    id: list_id,
+                    name: list.value().name,
+                    shares: shares.remove(&list_id).unwrap_or_default(),
+                });
+
+                // depth first, like `read_items`, so parents come first
+                let mut stack = vec![item_order_table.get(list_id)?];
+                while let Some(siblings) = stack.last_mut() {
+                    let Some(entry) = siblings.next() else {
+                        stack.pop();
+                        continue;
+                    };
+                    let (sort_id, item_id) = entry?.value();
+                    let item = item_table
+                        .get(item_id)?
+                        .ok_or_else(|| format_err!("ordered item not found"))?
+                        .value();
+                    items.push(ExportItem {
+                        id: item_id,
+                        list: list_id,
+                        parent: item.parent,
+                        sort_id: util::hex_encode(sort_id.as_bytes()),
+                        revision: item.revision,
+                        data: item.data,
+                    });
+                    stack.push(item_child_order_table.get(item_id)?);
+                }
+            }
+
+            Ok(Export {
+                version: EXPORT_VERSION,
+                users,
+                lists,
+                items,
+            })
+        })
+    }
+
+    /// Replace all users, lists and items with the ones in `export`
+    ///
+    /// Item history, the trash and all sessions are cleared too, as they
+    /// could refer to users and items that are gone.
+    pub fn import_db(&self, export: &Export) -> anyhow::Result<()> {
+        export.check()?;
+        self.db.write_with(|dbtx| {
+            dbtx.delete_table(USER_TABLE)?;
+            dbtx.delete_table(USER_NAME_TABLE)?;
+            dbtx.delete_table(SESSION_TABLE)?;
+            dbtx.delete_table(LIST_TABLE)?;
+            dbtx.delete_table(LIST_ACCESS_TABLE)?;
+            dbtx.delete_table(LIST_USER_TABLE)?;
+            dbtx.delete_table(ITEM_TABLE)?;
+            dbtx.delete_table(LAST_ITEM_ID_TABLE)?;
+            dbtx.delete_multimap_table(ITEM_ORDER_TABLE)?;
+            dbtx.delete_multimap_table(ITEM_CHILD_ORDER_TABLE)?;
+            dbtx.delete_table(ITEM_TRASH_TABLE)?;
+            dbtx.delete_table(ITEM_EVENT_TABLE)?;
+            dbtx.delete_table(UNDO_TABLE)?;
+            dbtx.delete_table(ITEM_TERM_TABLE)?;
+            dbtx.delete_table(ITEM_TAG_TABLE)?;
+            dbtx.delete_table(ITEM_DUE_TABLE)?;
+
+            let mut user_table = dbtx.open_table(USER_TABLE)?;
+            let mut user_name_table = dbtx.open_table(USER_NAME_TABLE)?;
+            for user in &export.users {
+                user_table.insert(user.id, &user.value)?;
+                user_name_table.insert(user.value.username.as_str(), user.id)?;
+            }
+
+            let mut list_table = dbtx.open_table(LIST_TABLE)?;
+            let mut list_access_tables = ListAccessTables::open(dbtx)?;
+            for list in &export.lists {
+                list_table.insert(
+                    list.id,
+                    ListValue {
+                        name: list.name.clone(),
+                    },
+                )?;
+                for share in &list.shares {
+                    list_access_tables.insert(share.user, list.id, share.access)?;
+                }
+            }
+
+            let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+            let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+            for item in &export.items {
+                let sort_id = item.decode_sort_id()?;
+                item_order_tables.insert(item.siblings(), (sort_id.clone(), item.id))?;
+                item_table.insert(
+                    item.id,
+                    ItemValue {
+                        list_id: item.list,
+                        sort_id,
+                        data: item.data.clone(),
+                        revision: item.revision,
+                        parent: item.parent,
+                    },
+                )?;
+                index_item(dbtx, item.id, None, Some(&item.data))?;
+            }
+            Ok(())
+        })?;
+        info!(
+            users = export.users.len(),
+            lists = export.lists.len(),
+            items = export.items.len(),
+            "Imported database"
+        );
+        Ok(())
+    }
+}
+
+/// 404 response for paths without a route, as json under `/api/`
+fn not_found(path: &str) -> astra::Response {
+    if path.starts_with("/api/") {
+        api::error_response(NotFoundError("route").into())
+    } else {
+        routes::not_found_404()
+    }
+}
+
+/// What kind of response a request asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseKind {
+    /// `Accept: application/json`
+    Json,
+    /// An htmx request, which wants just the html fragment to swap in
+    Fragment,
+    /// A full html page
+    Page,
 }
 
-pub struct RequestExt<'a>(&'a hyper::Request<astra::Body>);
+pub struct RequestExt<'a>(pub &'a hyper::Request<astra::Body>);
 
 impl<'a> RequestExt<'a> {
+    pub fn response_kind(&self) -> ResponseKind {
+        let headers = self.0.headers();
+        if headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().starts_with("application/json"))
+        {
+            ResponseKind::Json
+        } else if headers.contains_key("HX-Request")
+            // htmx restoring history it did not have cached needs the whole page
+            && !headers.contains_key("HX-History-Restore-Request")
+        {
+            ResponseKind::Fragment
+        } else {
+            ResponseKind::Page
+        }
+    }
+
     fn iter_cookies(&self) -> impl Iterator<Item = (&str, &str)> {
         self.0
             .headers()
@@ -334,7 +2666,7 @@ impl astra::Service for Service {
             "request received"
         );
         let (resp, peer_addr) = self.handle_rate_limiting(&mut req, &info, |req| {
-            self.handle_session(req, |req| self.route(req))
+            self.handle_session(req, |req, session| self.route(req, session))
         });
 
         use crate::util::DisplayOption;
@@ -348,3 +2680,175 @@ impl astra::Service for Service {
         resp
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    /// Run `f` with options pointing at a fresh database file, removed after
+    pub fn with_temp_db<T>(f: impl FnOnce(&opts::Opts) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let path = std::env::temp_dir().join(format!(
+            "htmx-sorta-{}.redb",
+            util::hex_encode(&util::random_bytes::<8>())
+        ));
+        let opts = opts::Opts::parse_from(["htmx-sorta", "--db", path.to_str().expect("utf-8")]);
+        let res = f(&opts);
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        res
+    }
+
+    fn data(title: &str) -> ItemData {
+        ItemData {
+            title: title.into(),
+            body: String::new(),
+            tags: vec![],
+            completed_at: None,
+            due: None,
+        }
+    }
+
+    fn titles(service: &Service, user_id: UserId, list_id: ListId) -> anyhow::Result<Vec<String>> {
+        Ok(service
+            .read_items(user_id, list_id)?
+            .into_iter()
+            .map(|item| item.data.title)
+            .collect())
+    }
+
+    #[test]
+    fn undo_checks_for_later_changes() -> anyhow::Result<()> {
+        with_temp_db(|opts| {
+            let service = Service::new(opts.clone())?;
+            let user_id = service.create_user("user", "password", true)?;
+            let other_id = service.create_user("other", "password", false)?;
+            let list_id = service.create_list(
+                user_id,
+                ListValue {
+                    name: "list".into(),
+                },
+            )?;
+            service.share_list(user_id, list_id, "other", Some(Access::Edit))?;
+            let a = service.create_item(user_id, list_id, data("a"))?;
+            let b = service.create_item(user_id, list_id, data("b"))?;
+            let c = service.create_item(user_id, list_id, data("c"))?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["c", "b", "a"]);
+
+            service.update_item(user_id, a, &data("a2"), None)?;
+            service.update_item(other_id, a, &data("a3"), None)?;
+            assert!(service
+                .undo(user_id)
+                .is_err_and(|error| error.is::<ConflictError>()));
+            assert_eq!(service.load_item(user_id, a)?.data.title, "a3");
+
+            // rebalancing changes the `SortId` the item had before moving
+            service.change_item_order(user_id, Some(a), c, None, None, None)?;
+            service.rebalance_all()?;
+            service.undo(user_id)?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["c", "b", "a3"]);
+
+            service.change_items_order(user_id, Some(a), &[c, b], None, None, None)?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["a3", "c", "b"]);
+            service.rebalance_all()?;
+            service.undo(user_id)?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["c", "b", "a3"]);
+
+            // each item's old neighbor is moved too, and comes later in the batch
+            service.change_items_order(user_id, Some(a), &[b, c], None, None, None)?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["a3", "b", "c"]);
+            service.undo(user_id)?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["c", "b", "a3"]);
+            assert!(service.fsck(false)?.is_empty());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn list_shares_follow_changes() -> anyhow::Result<()> {
+        with_temp_db(|opts| {
+            let service = Service::new(opts.clone())?;
+            let user_id = service.create_user("user", "password", true)?;
+            let other_id = service.create_user("other", "password", false)?;
+            let shares = |service: &Service| -> anyhow::Result<Vec<_>> {
+                Ok(service
+                    .read_list_shares(user_id, DEFAULT_LIST_ID)?
+                    .into_iter()
+                    .map(|(user_id, _, access)| (user_id, access))
+                    .collect())
+            };
+            assert_eq!(shares(&service)?, [(user_id, Access::Owner)]);
+
+            service.share_list(user_id, DEFAULT_LIST_ID, "other", Some(Access::Read))?;
+            service.create_inbox_if_missing(other_id)?;
+            assert_eq!(
+                shares(&service)?,
+                [(user_id, Access::Owner), (other_id, Access::Read)]
+            );
+            service.share_list(user_id, DEFAULT_LIST_ID, "other", None)?;
+            assert_eq!(shares(&service)?, [(user_id, Access::Owner)]);
+            // the share kept other from getting an inbox
+            assert!(service.read_lists(other_id)?.is_empty());
+            service.create_inbox_if_missing(other_id)?;
+            assert_eq!(service.read_lists(other_id)?.len(), 1);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn reorder_between_duplicate_sort_ids() -> anyhow::Result<()> {
+        with_temp_db(|opts| {
+            let service = Service::new(opts.clone())?;
+            let user_id = service.create_user("user", "password", true)?;
+            let list_id = DEFAULT_LIST_ID;
+            let a = service.create_item(user_id, list_id, data("a"))?;
+            let b = service.create_item(user_id, list_id, data("b"))?;
+            let c = service.create_item(user_id, list_id, data("c"))?;
+            // give c the `SortId` of b, which puts it right after b
+            service.db.write_with(|dbtx| {
+                let mut item_table = dbtx.open_table(ITEM_TABLE)?;
+                let mut item_order_tables = ItemOrderTables::open(dbtx)?;
+                let sort_id = item_table.get(b)?.expect("item").value().sort_id;
+                let item = item_table.get(c)?.expect("item").value();
+                item_order_tables.remove(item.siblings(), (item.sort_id.clone(), c))?;
+                item_order_tables.insert(item.siblings(), (sort_id.clone(), c))?;
+                item_table.insert(c, ItemValue { sort_id, ..item })?;
+                Ok(())
+            })?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["b", "c", "a"]);
+
+            service.change_items_order(user_id, Some(b), &[a], Some(c), None, None)?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["b", "a", "c"]);
+            assert!(service.fsck(false)?.is_empty());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn sub_items_share_the_trash_with_their_parent() -> anyhow::Result<()> {
+        with_temp_db(|opts| {
+            let service = Service::new(opts.clone())?;
+            let user_id = service.create_user("user", "password", true)?;
+            let list_id = DEFAULT_LIST_ID;
+            let a = service.create_item(user_id, list_id, data("a"))?;
+            let b = service.create_item(user_id, list_id, data("b"))?;
+            let c = service.create_item(user_id, list_id, data("c"))?;
+            service.change_item_order(user_id, None, b, None, None, Some(a))?;
+            service.change_item_order(user_id, None, c, None, None, Some(b))?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["a", "b", "c"]);
+
+            service.delete_item(user_id, a)?;
+            assert!(titles(&service, user_id, list_id)?.is_empty());
+            assert_eq!(service.read_trash(user_id)?.len(), 3);
+
+            service.restore_item(user_id, a)?;
+            assert_eq!(titles(&service, user_id, list_id)?, ["a", "b", "c"]);
+            assert_eq!(service.load_item(user_id, c)?.parent, Some(b));
+            assert!(service.read_trash(user_id)?.is_empty());
+            assert!(service.fsck(false)?.is_empty());
+            Ok(())
+        })
+    }
+}
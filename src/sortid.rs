@@ -2,7 +2,7 @@ use std::{cmp, debug_assert, mem};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// An ordering key that can always find a key between two other keys.
 ///
 /// The trick here is to treat end of array as a value 127.5, so it's
@@ -363,6 +363,66 @@ impl SortId {
 
         SortId(r)
     }
+
+    /// Generate `n` strictly increasing `SortId`s between `a` and `b`
+    ///
+    /// A missing bound means the keys are not bounded from that side, like
+    /// in [`SortId::in_front`] and [`SortId::at_the_end`]. The gap is bisected
+    /// recursively, so key length grows only logarithmically with `n`,
+    /// unlike with calling [`SortId::between`] in a loop.
+    ///
+    /// Panics if `a` and `b` are equal, there is no key between them. Callers
+    /// rebalance such neighbors first.
+    pub fn n_between(a: Option<&SortId>, b: Option<&SortId>, n: usize) -> Vec<SortId> {
+        assert!(a.is_none() || a != b, "no SortId between equal ones");
+        let mut res = Vec::with_capacity(n);
+        Self::n_between_into(a, b, n, &mut res);
+        res
+    }
+
+    fn n_between_into(a: Option<&SortId>, b: Option<&SortId>, n: usize, res: &mut Vec<SortId>) {
+        if n == 0 {
+            return;
+        }
+
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) if b < a => (Some(b), Some(a)),
+            ab => ab,
+        };
+
+        let mid = match (a, b) {
+            (Some(a), Some(b)) => SortId::between(a, b),
+            (Some(a), None) => SortId::at_the_end(Some(a)),
+            (None, Some(b)) => SortId::in_front(Some(b)),
+            (None, None) => SortId::from(vec![]),
+        };
+
+        let left_n = (n - 1) / 2;
+        Self::n_between_into(a, Some(&mid), left_n, res);
+        let right_n = n - 1 - left_n;
+        res.push(mid.clone());
+        Self::n_between_into(Some(&mid), b, right_n, res);
+    }
+
+    /// Generate `n` strictly increasing, evenly spaced `SortId`s
+    ///
+    /// All the keys have the same, shortest possible length, which makes
+    /// this useful for compacting keys that grew long over time.
+    pub fn evenly_spaced(n: usize) -> Vec<SortId> {
+        let n = n as u128;
+        let mut len = 1;
+        while (1u128 << (8 * len)) <= n {
+            len += 1;
+        }
+        let space = 1u128 << (8 * len);
+
+        (1..=n)
+            .map(|i| {
+                let pos = i * space / (n + 1);
+                SortId(pos.to_be_bytes()[16 - len..].to_vec())
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -387,6 +447,60 @@ mod tests {
         );
     }
 
+    #[quickcheck]
+    fn evenly_spaced_sorts_correctly_quickcheck(n: u16) {
+        let ids = SortId::evenly_spaced(n as usize);
+        assert_eq!(ids.len(), n as usize);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]), "{n}");
+        assert!(ids.iter().all(|id| id.as_bytes().len() <= 2), "{n}");
+    }
+
+    fn n_between_sorts_correctly_impl(a: Option<SortId>, b: Option<SortId>, n: usize) -> bool {
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) if b < a => (Some(b), Some(a)),
+            ab => ab,
+        };
+        let r = SortId::n_between(a.as_ref(), b.as_ref(), n);
+
+        r.len() == n
+            && r.windows(2).all(|w| w[0] < w[1])
+            && a.iter().all(|a| r.iter().all(|r| a < r))
+            && b.iter().all(|b| r.iter().all(|r| r < b))
+    }
+
+    #[quickcheck]
+    fn n_between_sorts_correctly_quickcheck(a: Option<Vec<u8>>, b: Option<Vec<u8>>, n: u8) {
+        if a.is_some() && a == b {
+            return;
+        }
+        assert!(
+            n_between_sorts_correctly_impl(
+                a.clone().map(SortId::from),
+                b.clone().map(SortId::from),
+                n as usize
+            ),
+            "{a:?}, {b:?}, {n}"
+        );
+    }
+
+    #[test]
+    fn n_between_keys_are_short() {
+        for (a, b, n, max_len) in [
+            (None, None, 1000, 3),
+            (Some(vec![0x10]), Some(vec![0x11]), 1000, 4),
+            (Some(vec![0x10]), None, 1000, 4),
+            (None, Some(vec![0x10]), 1000, 4),
+        ] {
+            let r = SortId::n_between(
+                a.clone().map(SortId::from).as_ref(),
+                b.clone().map(SortId::from).as_ref(),
+                n,
+            );
+            let len = r.iter().map(|r| r.as_bytes().len()).max().unwrap_or(0);
+            assert!(len <= max_len, "{a:?}, {b:?}, {n}: {len}");
+        }
+    }
+
     #[test]
     fn midpoint_sorts_correctly_manual() {
         for (a, b) in [
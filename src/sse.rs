@@ -0,0 +1,148 @@
+//! Server-Sent Events, telling open lists about changes to their items
+
+use std::io::{self, Read};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::db::{ListId, SessionId, UserId};
+
+/// How often an idle stream sends a comment, to find out the client is gone
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a stream stays open before it ends and the browser reconnects
+///
+/// Every open stream blocks one of the http server's worker threads, and
+/// access to the list is only checked when it's opened, so this bounds how
+/// long both last.
+const STREAM_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
+struct Event {
+    name: String,
+    data: String,
+}
+
+impl Event {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!("event: {}\n", self.name);
+        for line in self.data.lines() {
+            out.push_str("data: ");
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push('\n');
+        out.into_bytes()
+    }
+}
+
+struct Subscriber {
+    list_id: ListId,
+    user_id: UserId,
+    session_id: SessionId,
+    /// Whether `user_id` can edit the list, which changes how items render
+    editable: bool,
+    tx: mpsc::Sender<Arc<Event>>,
+}
+
+/// Fans out events of each list to all of its subscribed streams
+#[derive(Default)]
+pub struct Broadcaster {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl Broadcaster {
+    /// Stream events of `list_id` to `user_id`, who must be able to read it
+    pub fn subscribe(
+        &self,
+        list_id: ListId,
+        user_id: UserId,
+        session_id: SessionId,
+        editable: bool,
+    ) -> EventStream {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .expect("lock poisoned")
+            .push(Subscriber {
+                list_id,
+                user_id,
+                session_id,
+                editable,
+                tx,
+            });
+        EventStream {
+            rx,
+            buf: vec![],
+            pos: 0,
+            ends_at: Instant::now() + STREAM_LIFETIME,
+        }
+    }
+
+    /// End the streams of `user_id` for `list_id`, after their access to it
+    /// changed
+    ///
+    /// The browser reconnects, which checks the access again.
+    pub fn close_user(&self, list_id: ListId, user_id: UserId) {
+        self.subscribers
+            .lock()
+            .expect("lock poisoned")
+            .retain(|sub| sub.list_id != list_id || sub.user_id != user_id);
+    }
+
+    /// End the streams opened in `session_id`, after it got logged out
+    pub fn close_session(&self, session_id: SessionId) {
+        self.subscribers
+            .lock()
+            .expect("lock poisoned")
+            .retain(|sub| sub.session_id != session_id);
+    }
+
+    /// Send an event to streams of `list_id`, dropping the closed ones
+    ///
+    /// `data` renders the event for streams that can edit the list, or can't.
+    pub fn send(&self, list_id: ListId, name: &str, data: impl Fn(bool) -> String) {
+        let [read_only, editable] = [false, true].map(|editable| {
+            Arc::new(Event {
+                name: name.to_owned(),
+                data: data(editable),
+            })
+        });
+        self.subscribers
+            .lock()
+            .expect("lock poisoned")
+            .retain(|sub| {
+                let event = if sub.editable { &editable } else { &read_only };
+                sub.list_id != list_id || sub.tx.send(event.clone()).is_ok()
+            });
+    }
+}
+
+/// `text/event-stream` body of a single subscriber
+///
+/// Reading blocks until there's an event to send, and ends after
+/// [`STREAM_LIFETIME`] or once the [`Broadcaster`] drops the subscriber.
+pub struct EventStream {
+    rx: mpsc::Receiver<Arc<Event>>,
+    buf: Vec<u8>,
+    pos: usize,
+    ends_at: Instant,
+}
+
+impl Read for EventStream {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.buf.len() <= self.pos {
+            let Some(left) = self.ends_at.checked_duration_since(Instant::now()) else {
+                return Ok(0);
+            };
+            self.buf = match self.rx.recv_timeout(left.min(KEEPALIVE_INTERVAL)) {
+                Ok(event) => event.to_bytes(),
+                Err(mpsc::RecvTimeoutError::Timeout) => b": keepalive\n\n".to_vec(),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.pos = 0;
+        }
+        let len = out.len().min(self.buf.len() - self.pos);
+        out[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
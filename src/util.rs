@@ -1,4 +1,10 @@
 use std::fmt;
+use std::fmt::Write as _;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, format_err};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 
 pub struct DisplayOption<T>(pub Option<T>);
 
@@ -13,3 +19,149 @@ where
         }
     }
 }
+
+/// Current time as a unix timestamp in seconds
+pub fn now_unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_secs()
+}
+
+/// Encode bytes as lowercase hex
+pub fn hex_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{b:02x}").expect("can't fail");
+    }
+    s
+}
+
+/// Decode hex (any case) into bytes
+pub fn hex_decode(s: &str) -> anyhow::Result<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|chunk| {
+            if chunk.len() != 2 {
+                bail!("odd number of hex digits");
+            }
+            if !chunk.iter().all(u8::is_ascii_hexdigit) {
+                bail!("invalid hex digit");
+            }
+            Ok(u8::from_str_radix(
+                std::str::from_utf8(chunk).expect("hex digits are ascii"),
+                16,
+            )?)
+        })
+        .collect()
+}
+
+/// Generate `N` cryptographically secure random bytes
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).expect("failed to get random bytes");
+    bytes
+}
+
+/// Compare secrets in time independent of where they differ
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Hash a password with a random salt, into a PHC string
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::encode_b64(&random_bytes::<16>()).map_err(|e| format_err!("{e}"))?;
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| format_err!("{e}"))?
+        .to_string())
+}
+
+/// Today's date in UTC, as `YYYY-MM-DD`
+pub fn today() -> String {
+    // days since 0000-03-01, so leap days end each 4/100/400 year cycle
+    let days = (now_unix_secs() / (24 * 60 * 60)) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Whether `date` is a valid `YYYY-MM-DD` date
+///
+/// Dates in this form compare in the same order as strings.
+pub fn is_valid_date(date: &str) -> bool {
+    let bytes = date.as_bytes();
+    let is_digit_or_dash = |(i, b): (usize, &u8)| match i {
+        4 | 7 => *b == b'-',
+        _ => b.is_ascii_digit(),
+    };
+    if bytes.len() != 10 || !bytes.iter().enumerate().all(is_digit_or_dash) {
+        return false;
+    }
+    let number = |range: std::ops::Range<usize>| -> u32 {
+        date[range].parse().expect("checked to be digits")
+    };
+    let (year, month, day) = (number(0..4), number(5..7), number(8..10));
+    let is_leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if is_leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return false,
+    };
+    (1..=days_in_month).contains(&day)
+}
+
+/// Check a password against a hash from [`hash_password`]
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[test]
+fn password_hash_roundtrip() {
+    let hash = hash_password("hunter2").unwrap();
+    assert!(verify_password("hunter2", &hash));
+    assert!(!verify_password("hunter3", &hash));
+    assert_ne!(hash, hash_password("hunter2").unwrap());
+    assert!(!verify_password("hunter2", "not a hash"));
+}
+
+#[test]
+fn hex_roundtrip() {
+    for bytes in [vec![], vec![0x00], vec![0x01, 0xab, 0xff]] {
+        assert_eq!(hex_decode(&hex_encode(&bytes)).unwrap(), bytes);
+    }
+    assert_eq!(hex_encode(&[0x0a, 0xf0]), "0af0");
+    assert_eq!(hex_decode("0AF0").unwrap(), vec![0x0a, 0xf0]);
+    assert!(hex_decode("0af").is_err());
+    assert!(hex_decode("zz").is_err());
+    assert!(hex_decode("+f").is_err());
+}
+
+#[test]
+fn dates() {
+    assert!(is_valid_date(&today()));
+    assert!(is_valid_date("2024-02-29"));
+    assert!(!is_valid_date("2023-02-29"));
+    assert!(!is_valid_date("2024-13-01"));
+    assert!(!is_valid_date("2024-1-01"));
+    assert!(!is_valid_date("2024-01-01-01"));
+    assert!(!is_valid_date("+024-01-01"));
+    assert!(!is_valid_date(""));
+}
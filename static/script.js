@@ -1,18 +1,73 @@
+function itemIds(elements) {
+    return Array.from(elements, (element) => element.id).join(',');
+}
+
+// Rows of `container` itself, not of the sub-items nested in them
+const SELECTED_ROWS = ':scope > .draggable:has(> .item-line > .item-select:checked)';
+const UNSELECTED_ROWS = ':scope > .draggable:not(:has(> .item-line > .item-select:checked))';
+
+// Move all selected items of `container` between `prev` and `next`
+function batchMove(container, selected, prev, next, list, parent) {
+    htmx.ajax('POST', '/item/order/batch', {
+        source: container,
+        // the response re-renders the whole list, sub-items included
+        target: '#items',
+        swap: 'outerHTML',
+        // round-trip through JSON to drop `undefined` values
+        values: JSON.parse(JSON.stringify({ prev, items: itemIds(selected), next, list, parent })),
+    });
+}
+
+function isSelected(row) {
+    return row?.querySelector(':scope > .item-line > .item-select:checked');
+}
+
 function installSortable(element) {
+      // list drop zones only accept items, they don't display them
+      const isListDrop = element.classList.contains('list-drop');
       new Sortable(element, {
           animation: 150,
           ghostClass: 'blue-background-class',
           handle: '.handle',
           draggable: '.draggable',
+          fallbackOnBody: true,
+          swapThreshold: 0.65,
+          group: { name: 'items', pull: !isListDrop, put: true },
           onEnd: function (evt) {
             const to = evt.to;
+            const list = to.dataset.list;
+            const parent = to.dataset.parent;
+            const from = evt.from;
+            const selected = from.querySelectorAll(SELECTED_ROWS);
+            const isBatch = from.closest('.multi-select')
+                && isSelected(evt.item)
+                && 1 < selected.length + (to === from ? 0 : 1);
+
+            if (isBatch) {
+                const batch = to === from
+                    ? Array.from(selected)
+                    : [evt.item, ...selected];
+                let prevElement = evt.item.previousElementSibling;
+                while (isSelected(prevElement)) {
+                    prevElement = prevElement.previousElementSibling;
+                }
+                let nextElement = evt.item.nextElementSibling;
+                while (isSelected(nextElement)) {
+                    nextElement = nextElement.nextElementSibling;
+                }
+                const prev = prevElement?.classList.contains('draggable') ? prevElement.id : undefined;
+                const next = nextElement?.classList.contains('draggable') ? nextElement.id : undefined;
+                batchMove(from, batch, prev, next, list, parent);
+                return;
+            }
+
             const eventDstElement = to;
             const prevElement = to.children[evt.newIndex - 1];
             const prevElementIsItem = prevElement?.classList.contains('draggable');
             const prev = prevElementIsItem ? prevElement?.id : undefined;
             const curr = to.children[evt.newIndex]?.id;
             const next = to.children[evt.newIndex + 1]?.id;
-            eventDstElement.setAttribute("hx-vals", JSON.stringify({ prev, curr, next }));
+            eventDstElement.setAttribute("hx-vals", JSON.stringify({ prev, curr, next, list, parent }));
             eventDstElement.dispatchEvent(new Event("changed"));
             eventDstElement.setAttribute("hx-vals", "");
           }
@@ -34,6 +89,98 @@ htmx.onLoad(function(content) {
   installSortableInChildren(content);
 })
 
+// a conflicting edit responds with a form to resolve the conflict
+document.body.addEventListener('htmx:beforeSwap', function(evt) {
+  if (evt.detail.xhr.status === 409) {
+    evt.detail.shouldSwap = true;
+    evt.detail.isError = false;
+  }
+});
+
 document.body.addEventListener('htmx:afterSwap', function(evt) {
   installSortableInChildren(evt.target);
 });
+
+document.body.addEventListener('click', function(evt) {
+    const container = evt.target.closest('.sortable');
+    if (!container) {
+        return;
+    }
+
+    if (evt.target.closest('.multi-select-toggle')) {
+        container.classList.toggle('multi-select');
+        return;
+    }
+
+    const batchMoveButton = evt.target.closest('[data-batch-move]');
+    if (batchMoveButton) {
+        const selected = container.querySelectorAll(SELECTED_ROWS);
+        if (selected.length === 0) {
+            return;
+        }
+        const rest = container.querySelectorAll(UNSELECTED_ROWS);
+        if (batchMoveButton.dataset.batchMove === 'top') {
+            batchMove(container, selected, undefined, rest[0]?.id, container.dataset.list, container.dataset.parent);
+        } else {
+            batchMove(container, selected, rest[rest.length - 1]?.id, undefined, container.dataset.list, container.dataset.parent);
+        }
+    }
+});
+
+// hiding completed items is remembered across page loads
+const HIDE_COMPLETED_KEY = 'hide-completed';
+
+htmx.onLoad(function(content) {
+  const toggle = content.querySelector('.hide-completed-toggle');
+  if (toggle && localStorage.getItem(HIDE_COMPLETED_KEY)) {
+    toggle.parentElement.classList.add('hide-completed');
+  }
+});
+
+document.body.addEventListener('click', function(evt) {
+    const toggle = evt.target.closest('.hide-completed-toggle');
+    if (toggle) {
+        const hide = toggle.parentElement.classList.toggle('hide-completed');
+        if (hide) {
+            localStorage.setItem(HIDE_COMPLETED_KEY, '1');
+        } else {
+            localStorage.removeItem(HIDE_COMPLETED_KEY);
+        }
+    }
+});
+
+// the chosen export file is read into the form, so it's posted like any
+// other form, CSRF token included
+document.body.addEventListener('change', async function(evt) {
+    const input = evt.target.closest('[data-import-file]');
+    if (input?.files[0]) {
+        input.form.elements.data.value = await input.files[0].text();
+    }
+});
+
+document.body.addEventListener('click', function(evt) {
+    if (evt.target.closest('[data-dismiss-toast]')) {
+        document.getElementById('toast').replaceChildren();
+    }
+});
+
+// failed requests swap nothing in, the toast tells about them instead
+document.body.addEventListener('htmx:responseError', function(evt) {
+    const toast = document.getElementById('toast');
+    if (!toast) {
+        return;
+    }
+    const xhr = evt.detail.xhr;
+    const isText = xhr.getResponseHeader('Content-Type')?.startsWith('text/plain');
+    const message = document.createElement('div');
+    message.className = 'px-3 py-2 flex gap-3 items-center shadow-md bg-white rounded-md border-1 border-solid';
+    const text = document.createElement('span');
+    text.textContent = isText ? xhr.responseText : 'Something went wrong';
+    const dismiss = document.createElement('button');
+    dismiss.className = 'text-gray-500';
+    dismiss.type = 'button';
+    dismiss.dataset.dismissToast = '';
+    dismiss.textContent = '×';
+    message.replaceChildren(text, dismiss);
+    toast.replaceChildren(message);
+});